@@ -1,4 +1,5 @@
 #version 450
+#pragma shader_stage(fragment)
 
 layout(location = 0) out vec4 f_color;
 
@@ -0,0 +1,146 @@
+use crate::compiler::ShaderCompiler;
+use crate::config::CompileConfig;
+use crate::error::{CompileError, Error};
+use crate::CompiledShader;
+use shaderc::ShaderKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// What a `BatchJob` compiles.
+#[derive(Clone, Debug)]
+pub enum BatchInput {
+    Path(PathBuf),
+    /// Source text and the file name used for it in diagnostics and includes.
+    Source { source: String, name: Option<PathBuf> },
+}
+
+/// One shader for `compile_batch`.
+#[derive(Clone, Debug)]
+pub struct BatchJob {
+    pub input: BatchInput,
+    pub kind: ShaderKind,
+    pub config: CompileConfig,
+}
+
+/// The result of one job and how long it took.
+pub struct JobResult {
+    pub result: Result<CompiledShader, Error>,
+    pub time: Duration,
+}
+
+pub struct BatchResult {
+    /// One result per job in the order the jobs were given.
+    pub results: Vec<JobResult>,
+    /// The time to compile the whole batch.
+    pub total: Duration,
+}
+
+impl BatchJob {
+    pub fn file<T>(path: T, kind: ShaderKind) -> Self
+    where
+        T: AsRef<Path>,
+    {
+        BatchJob {
+            input: BatchInput::Path(path.as_ref().to_path_buf()),
+            kind,
+            config: CompileConfig::default(),
+        }
+    }
+
+    pub fn source<T>(source: &str, name: Option<T>, kind: ShaderKind) -> Self
+    where
+        T: AsRef<Path>,
+    {
+        BatchJob {
+            input: BatchInput::Source {
+                source: source.to_string(),
+                name: name.map(|n| n.as_ref().to_path_buf()),
+            },
+            kind,
+            config: CompileConfig::default(),
+        }
+    }
+
+    pub fn config(mut self, config: CompileConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    fn run(&self, compiler: &mut ShaderCompiler) -> Result<CompiledShader, Error> {
+        compiler.set_config(self.config.clone());
+        match &self.input {
+            BatchInput::Path(path) => compiler.load(path, self.kind),
+            BatchInput::Source { source, name } => {
+                compiler.load_from_string(source, name.as_ref(), self.kind)
+            }
+        }
+    }
+}
+
+/// Compiles the jobs with one thread per CPU.
+pub fn compile_batch(jobs: Vec<BatchJob>) -> BatchResult {
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    compile_batch_with_threads(jobs, threads)
+}
+
+/// Compiles the jobs on up to `threads` threads.
+/// Each thread has its own compiler session and takes the next job
+/// as soon as it finishes one, so slow shaders don't hold up the rest.
+pub fn compile_batch_with_threads(jobs: Vec<BatchJob>, threads: usize) -> BatchResult {
+    let start = Instant::now();
+    let len = jobs.len();
+    let jobs = Arc::new(jobs);
+    let next = Arc::new(AtomicUsize::new(0));
+    let (tx, rx) = mpsc::channel();
+    let handles = (0..threads.max(1).min(len))
+        .map(|_| {
+            let jobs = jobs.clone();
+            let next = next.clone();
+            let tx = tx.clone();
+            thread::spawn(move || {
+                let mut compiler = ShaderCompiler::new();
+                loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    let job = match jobs.get(i) {
+                        Some(job) => job,
+                        None => break,
+                    };
+                    let job_start = Instant::now();
+                    let result = match &mut compiler {
+                        Ok(compiler) => job.run(compiler),
+                        Err(_) => Err(Error::Compile(CompileError::CreateCompiler)),
+                    };
+                    let time = job_start.elapsed();
+                    if tx.send((i, JobResult { result, time })).is_err() {
+                        break;
+                    }
+                }
+            })
+        })
+        .collect::<Vec<_>>();
+    drop(tx);
+
+    let mut results = (0..len).map(|_| None).collect::<Vec<Option<JobResult>>>();
+    for (i, result) in rx.iter() {
+        results[i] = Some(result);
+    }
+    for handle in handles {
+        handle.join().ok();
+    }
+    let results = results
+        .into_iter()
+        .map(|result| {
+            result.unwrap_or_else(|| JobResult {
+                result: Err(Error::LoadingData("The compile thread stopped".to_string())),
+                time: Duration::default(),
+            })
+        })
+        .collect();
+    BatchResult {
+        results,
+        total: start.elapsed(),
+    }
+}
@@ -0,0 +1,720 @@
+use crate::layouts::{Entry, Input, Layout, Output};
+use crate::reflection::LayoutData;
+use crate::srvk::FORMATS;
+use crate::vk::descriptor::descriptor::*;
+use crate::vk::descriptor::pipeline_layout::PipelineLayoutDescPcRange;
+use crate::vk::format::Format;
+use crate::vk::pipeline::shader::ShaderInterfaceDefEntry;
+use shaderc::{CompileOptions, OptimizationLevel, ShaderKind};
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::Hasher;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+const MAGIC: &[u8; 4] = b"SRC4";
+const EXTENSION: &str = "spvcache";
+
+/// Makes the temporary file of each write unique within the process.
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// The stages an entry can be reflected as, stored by their index.
+const KINDS: [ShaderKind; 8] = [
+    ShaderKind::Vertex,
+    ShaderKind::Fragment,
+    ShaderKind::Compute,
+    ShaderKind::Geometry,
+    ShaderKind::TessControl,
+    ShaderKind::TessEvaluation,
+    ShaderKind::Mesh,
+    ShaderKind::Task,
+];
+
+/// Compiled by `compiler_version` to tell compilers apart.
+const PROBE: &str = "#version 450
+layout(location = 0) in vec4 v_color;
+layout(location = 0) out vec4 f_color;
+
+void main() {
+  vec4 color = v_color;
+  for (int i = 0; i < 4; i++) {
+    color = color.x > 0.5 ? sin(color) : color * 2.0;
+  }
+  f_color = color;
+}
+";
+
+/// Counts of cache lookups since the cache was created.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries removed to keep the cache under its size limit.
+    pub evictions: u64,
+}
+
+/// A directory of compiled SPIR-V kept between runs.
+///
+/// Entries are keyed by a hash of the file name, the preprocessed source,
+/// the paths of every included file, the compile settings, the stage and the compiler
+/// that is linked in, so any change to them is a miss. Each entry holds
+/// the SPIR-V, the warnings and the reflected `Entry` with its stage,
+/// so a hit doesn't need to reflect the module again.
+///
+/// Clones share their stats so a clone given to a `CompileConfig`
+/// can be used to check the hit rate of every compile using it.
+/// The cache never fails a compile: entries that can't be read or
+/// written are treated as misses.
+#[derive(Clone, Debug)]
+pub struct CompileCache {
+    dir: PathBuf,
+    max_size: Option<u64>,
+    stats: Arc<Mutex<CacheStats>>,
+    /// Identifies the linked compiler. `None` if it couldn't be
+    /// identified, in which case the cache isn't used.
+    compiler: Option<String>,
+    /// The size of the directory at the last scan plus everything
+    /// written since. `None` until the first scan.
+    size: Arc<Mutex<Option<u64>>>,
+}
+
+/// Identifies a compile in the cache.
+pub(crate) struct Key {
+    /// The file name of the entry.
+    name: String,
+    /// Stored in the entry so a hit on an entry whose name collided is
+    /// rejected. The total length of the parts and a second hash of them.
+    check: (u64, u64),
+}
+
+/// A compile result read from the cache.
+pub(crate) struct CachedShader {
+    pub(crate) spirv: Vec<u32>,
+    pub(crate) warnings: String,
+    /// The stage the module was reflected as and its entry.
+    /// `None` if the module couldn't be reflected when it was compiled.
+    pub(crate) entry: Option<(ShaderKind, Entry)>,
+}
+
+impl CompileCache {
+    /// The directory is created on the first write.
+    /// This compiles a small shader to identify the compiler.
+    pub fn new<T>(dir: T) -> Self
+    where
+        T: AsRef<Path>,
+    {
+        CompileCache {
+            dir: dir.as_ref().to_path_buf(),
+            max_size: None,
+            stats: Arc::new(Mutex::new(CacheStats::default())),
+            compiler: compiler_version(),
+            size: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Removes the least recently used entries once the cache holds more than `bytes`.
+    pub fn max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats.lock().map(|s| *s).unwrap_or_default()
+    }
+
+    /// Removes every entry.
+    pub fn clear(&self) -> io::Result<()> {
+        for (path, _, _) in self.entries() {
+            fs::remove_file(path)?;
+        }
+        if let Ok(mut size) = self.size.lock() {
+            *size = Some(0);
+        }
+        Ok(())
+    }
+
+    /// The key for a compile. `parts` are everything the result depends on.
+    /// `None` if the cache can't be used.
+    pub(crate) fn key(&self, parts: &[&[u8]]) -> Option<Key> {
+        let compiler = self.compiler.as_ref()?;
+        let version = format!("{} {}", env!("CARGO_PKG_VERSION"), compiler);
+        let parts = std::iter::once(version.as_bytes()).chain(parts.iter().cloned());
+        let len = parts.clone().map(|part| part.len() as u64).sum();
+        Some(Key {
+            name: format!("{:016x}", fnv(parts.clone())),
+            check: (len, sip(parts)),
+        })
+    }
+
+    pub(crate) fn get(&self, key: &Key) -> Option<CachedShader> {
+        let path = self.path(&key.name);
+        let shader = fs::read(&path).ok().and_then(|bytes| decode(&bytes, key.check));
+        if shader.is_some() {
+            // Eviction goes by modification time so a hit marks the entry as used.
+            fs::OpenOptions::new()
+                .write(true)
+                .open(&path)
+                .and_then(|file| file.set_modified(SystemTime::now()))
+                .ok();
+        }
+        if let Ok(mut stats) = self.stats.lock() {
+            match shader {
+                Some(_) => stats.hits += 1,
+                None => stats.misses += 1,
+            }
+        }
+        shader
+    }
+
+    pub(crate) fn insert(&self, key: &Key, spirv: &[u32], warnings: &str, entry: Option<&(ShaderKind, Entry)>) {
+        if fs::create_dir_all(&self.dir).is_err() {
+            return;
+        }
+        // Write to a temporary file first so a reader never sees half an entry.
+        // Its name is unique so other threads and processes writing the
+        // same entry don't write into the same file.
+        let path = self.path(&key.name);
+        let count = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let temp = self.dir.join(format!("{}.{}.{}.tmp", key.name, process::id(), count));
+        let bytes = encode(key.check, spirv, warnings, entry);
+        if fs::write(&temp, &bytes).is_err() {
+            fs::remove_file(&temp).ok();
+            return;
+        }
+        if fs::rename(&temp, &path).is_err() {
+            fs::remove_file(&temp).ok();
+            return;
+        }
+        self.added(bytes.len() as u64);
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(key).with_extension(EXTENSION)
+    }
+
+    /// Every entry with its size and modification time.
+    fn entries(&self) -> Vec<(PathBuf, u64, SystemTime)> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|ext| ext == EXTENSION))
+            .filter_map(|p| {
+                let metadata = p.metadata().ok()?;
+                let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                Some((p, metadata.len(), modified))
+            })
+            .collect()
+    }
+
+    /// Evicts entries if writing `len` bytes may have gone over the limit.
+    /// The directory is only scanned then, or the first time.
+    fn added(&self, len: u64) {
+        let max_size = match self.max_size {
+            Some(max_size) => max_size,
+            None => return,
+        };
+        let over = match self.size.lock() {
+            Ok(mut size) => {
+                *size = size.map(|size| size + len);
+                match *size {
+                    Some(size) => size > max_size,
+                    None => true,
+                }
+            }
+            Err(_) => true,
+        };
+        if over {
+            self.evict(max_size);
+        }
+    }
+
+    /// Removes the least recently used entries until the cache fits in `max_size`.
+    fn evict(&self, max_size: u64) {
+        let mut entries = self.entries();
+        let mut size = entries.iter().map(|(_, len, _)| len).sum::<u64>();
+        entries.sort_by_key(|(_, _, modified)| *modified);
+        for (path, len, _) in entries {
+            if size <= max_size {
+                break;
+            }
+            if fs::remove_file(&path).is_ok() {
+                size -= len;
+                if let Ok(mut stats) = self.stats.lock() {
+                    stats.evictions += 1;
+                }
+            }
+        }
+        if let Ok(mut cached) = self.size.lock() {
+            *cached = Some(size);
+        }
+    }
+}
+
+/// A 64 bit FNV-1a hash of `parts`.
+fn fnv<'a, I>(parts: I) -> u64
+where
+    I: Iterator<Item = &'a [u8]>,
+{
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for part in parts {
+        // Hash the length too so the parts can't run together.
+        for byte in (part.len() as u64).to_le_bytes().iter().chain(part) {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+/// A SipHash of `parts`, which is unrelated to `fnv`.
+/// std doesn't promise the same hash between Rust versions,
+/// but a change only turns hits into misses.
+fn sip<'a, I>(parts: I) -> u64
+where
+    I: Iterator<Item = &'a [u8]>,
+{
+    let mut hasher = DefaultHasher::new();
+    for part in parts {
+        hasher.write_u64(part.len() as u64);
+        hasher.write(part);
+    }
+    hasher.finish()
+}
+
+/// Identifies the shaderc library that is linked in.
+/// shaderc can't report its own version, so this hashes the output of
+/// compiling `PROBE` with and without optimization. That changes with
+/// the glslang and SPIRV-Tools versions, and the SPIR-V header holds
+/// the generator version of the front end.
+fn compiler_version() -> Option<String> {
+    let mut compiler = shaderc::Compiler::new()?;
+    let mut outputs = Vec::new();
+    for level in &[OptimizationLevel::Zero, OptimizationLevel::Performance] {
+        let mut options = CompileOptions::new()?;
+        options.set_optimization_level(*level);
+        let result = compiler
+            .compile_into_spirv(PROBE, ShaderKind::Fragment, "probe", "main", Some(&options))
+            .ok()?;
+        outputs.push(result.as_binary_u8().to_vec());
+    }
+    let version = format!("{:?}", shaderc::get_spirv_version());
+    let parts = std::iter::once(version.as_bytes()).chain(outputs.iter().map(|o| o.as_slice()));
+    Some(format!("{:016x}{:016x}", fnv(parts.clone()), sip(parts)))
+}
+
+/// The magic, the check of the key, the SPIR-V words, the warnings
+/// and then the entry if there is one.
+fn encode(check: (u64, u64), spirv: &[u32], warnings: &str, entry: Option<&(ShaderKind, Entry)>) -> Vec<u8> {
+    let mut w = Writer(Vec::with_capacity(32 + spirv.len() * 4 + warnings.len()));
+    w.0.extend_from_slice(MAGIC);
+    w.u64(check.0);
+    w.u64(check.1);
+    w.u32(spirv.len() as u32);
+    for word in spirv {
+        w.u32(*word);
+    }
+    w.bytes(warnings.as_bytes());
+    // An entry with a format or stage that can't be stored is left out.
+    let mut with_entry = Writer(Vec::new());
+    match entry.and_then(|(kind, entry)| {
+        with_entry.u8(KINDS.iter().position(|k| k == kind)? as u8);
+        write_entry(&mut with_entry, entry)
+    }) {
+        Some(()) => {
+            w.bool(true);
+            w.0.extend_from_slice(&with_entry.0);
+        }
+        None => w.bool(false),
+    }
+    w.0
+}
+
+/// `None` if the entry is bad or was written for a key with another `check`.
+fn decode(bytes: &[u8], check: (u64, u64)) -> Option<CachedShader> {
+    let mut r = Reader(bytes);
+    if r.take(4)? != MAGIC || (r.u64()?, r.u64()?) != check {
+        return None;
+    }
+    let len = r.u32()? as usize;
+    let spirv = (0..len).map(|_| r.u32()).collect::<Option<Vec<_>>>()?;
+    let warnings = r.string()?;
+    let entry = if r.bool()? {
+        let kind = *KINDS.get(r.u8()? as usize)?;
+        Some((kind, read_entry(&mut r)?))
+    } else {
+        None
+    };
+    Some(CachedShader { spirv, warnings, entry })
+}
+
+/// Writes values in little endian.
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.0.push(value);
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn usize(&mut self, value: usize) {
+        self.u64(value as u64);
+    }
+
+    fn bytes(&mut self, value: &[u8]) {
+        self.u32(value.len() as u32);
+        self.0.extend_from_slice(value);
+    }
+}
+
+/// Reads what a `Writer` wrote. Every read is `None` past the end.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    fn bool(&mut self) -> Option<bool> {
+        match self.u8()? {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        }
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.take(4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        let b = self.take(8)?;
+        Some(u64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]]))
+    }
+
+    fn usize(&mut self) -> Option<usize> {
+        self.u64().map(|value| value as usize)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).ok()
+    }
+}
+
+/// `None` if the entry has a format that isn't in `FORMATS`.
+fn write_entry(w: &mut Writer, entry: &Entry) -> Option<()> {
+    write_interface(w, entry.input.as_ref().map(|input| &input.inputs[..]))?;
+    write_interface(w, entry.output.as_ref().map(|output| &output.outputs[..]))?;
+    let layout = &entry.layout.layout_data;
+    w.usize(layout.num_sets);
+    w.usize(layout.num_bindings.len());
+    for (set, count) in &layout.num_bindings {
+        w.usize(*set);
+        w.usize(*count);
+    }
+    w.usize(layout.descriptions.len());
+    for (set, descriptors) in &layout.descriptions {
+        w.usize(*set);
+        w.usize(descriptors.len());
+        for (binding, descriptor) in descriptors {
+            w.usize(*binding);
+            write_descriptor(w, descriptor)?;
+        }
+    }
+    w.usize(layout.num_constants);
+    w.usize(layout.pc_ranges.len());
+    for range in &layout.pc_ranges {
+        w.usize(range.offset);
+        w.usize(range.size);
+        write_stages(w, &range.stages);
+    }
+    Some(())
+}
+
+fn read_entry(r: &mut Reader) -> Option<Entry> {
+    let input = read_interface(r)?.map(|inputs| Input { inputs });
+    let output = read_interface(r)?.map(|outputs| Output { outputs });
+    let num_sets = r.usize()?;
+    let num_bindings = (0..r.usize()?)
+        .map(|_| Some((r.usize()?, r.usize()?)))
+        .collect::<Option<HashMap<_, _>>>()?;
+    let descriptions = (0..r.usize()?)
+        .map(|_| {
+            let set = r.usize()?;
+            let descriptors = (0..r.usize()?)
+                .map(|_| Some((r.usize()?, read_descriptor(r)?)))
+                .collect::<Option<HashMap<_, _>>>()?;
+            Some((set, descriptors))
+        })
+        .collect::<Option<HashMap<_, _>>>()?;
+    let num_constants = r.usize()?;
+    let pc_ranges = (0..r.usize()?)
+        .map(|_| {
+            Some(PipelineLayoutDescPcRange {
+                offset: r.usize()?,
+                size: r.usize()?,
+                stages: read_stages(r)?,
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(Entry {
+        input,
+        output,
+        layout: Layout {
+            layout_data: LayoutData {
+                num_sets,
+                num_bindings,
+                descriptions,
+                num_constants,
+                pc_ranges,
+            },
+        },
+    })
+}
+
+fn write_interface(w: &mut Writer, interface: Option<&[ShaderInterfaceDefEntry]>) -> Option<()> {
+    let interface = match interface {
+        Some(interface) => interface,
+        None => {
+            w.bool(false);
+            return Some(());
+        }
+    };
+    w.bool(true);
+    w.usize(interface.len());
+    for element in interface {
+        w.u32(element.location.start);
+        w.u32(element.location.end);
+        write_format(w, Some(element.format))?;
+        match &element.name {
+            Some(name) => {
+                w.bool(true);
+                w.bytes(name.as_bytes());
+            }
+            None => w.bool(false),
+        }
+    }
+    Some(())
+}
+
+/// The outer `Option` is `None` if the entry is bad and
+/// the inner one is `None` if there was no interface.
+fn read_interface(r: &mut Reader) -> Option<Option<Vec<ShaderInterfaceDefEntry>>> {
+    if !r.bool()? {
+        return Some(None);
+    }
+    let elements = (0..r.usize()?)
+        .map(|_| {
+            let location = r.u32()?..r.u32()?;
+            let format = read_format(r)??;
+            let name = if r.bool()? { Some(Cow::Owned(r.string()?)) } else { None };
+            Some(ShaderInterfaceDefEntry {
+                location,
+                format,
+                name,
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(Some(elements))
+}
+
+fn write_descriptor(w: &mut Writer, descriptor: &DescriptorDesc) -> Option<()> {
+    match &descriptor.ty {
+        DescriptorDescTy::Sampler => w.u8(0),
+        DescriptorDescTy::CombinedImageSampler(image) => {
+            w.u8(1);
+            write_image(w, image)?;
+        }
+        DescriptorDescTy::Image(image) => {
+            w.u8(2);
+            write_image(w, image)?;
+        }
+        DescriptorDescTy::TexelBuffer { storage, format } => {
+            w.u8(3);
+            w.bool(*storage);
+            write_format(w, *format)?;
+        }
+        DescriptorDescTy::InputAttachment {
+            multisampled,
+            array_layers,
+        } => {
+            w.u8(4);
+            w.bool(*multisampled);
+            write_array(w, array_layers);
+        }
+        DescriptorDescTy::Buffer(buffer) => {
+            w.u8(5);
+            match buffer.dynamic {
+                Some(dynamic) => w.u8(dynamic as u8),
+                None => w.u8(2),
+            }
+            w.bool(buffer.storage);
+        }
+    }
+    w.u32(descriptor.array_count);
+    write_stages(w, &descriptor.stages);
+    w.bool(descriptor.readonly);
+    Some(())
+}
+
+fn read_descriptor(r: &mut Reader) -> Option<DescriptorDesc> {
+    let ty = match r.u8()? {
+        0 => DescriptorDescTy::Sampler,
+        1 => DescriptorDescTy::CombinedImageSampler(read_image(r)?),
+        2 => DescriptorDescTy::Image(read_image(r)?),
+        3 => DescriptorDescTy::TexelBuffer {
+            storage: r.bool()?,
+            format: read_format(r)?,
+        },
+        4 => DescriptorDescTy::InputAttachment {
+            multisampled: r.bool()?,
+            array_layers: read_array(r)?,
+        },
+        5 => DescriptorDescTy::Buffer(DescriptorBufferDesc {
+            dynamic: match r.u8()? {
+                0 => Some(false),
+                1 => Some(true),
+                2 => None,
+                _ => return None,
+            },
+            storage: r.bool()?,
+        }),
+        _ => return None,
+    };
+    Some(DescriptorDesc {
+        ty,
+        array_count: r.u32()?,
+        stages: read_stages(r)?,
+        readonly: r.bool()?,
+    })
+}
+
+fn write_image(w: &mut Writer, image: &DescriptorImageDesc) -> Option<()> {
+    w.bool(image.sampled);
+    w.u8(match image.dimensions {
+        DescriptorImageDescDimensions::OneDimensional => 0,
+        DescriptorImageDescDimensions::TwoDimensional => 1,
+        DescriptorImageDescDimensions::ThreeDimensional => 2,
+        DescriptorImageDescDimensions::Cube => 3,
+    });
+    write_format(w, image.format)?;
+    w.bool(image.multisampled);
+    write_array(w, &image.array_layers);
+    Some(())
+}
+
+fn read_image(r: &mut Reader) -> Option<DescriptorImageDesc> {
+    Some(DescriptorImageDesc {
+        sampled: r.bool()?,
+        dimensions: match r.u8()? {
+            0 => DescriptorImageDescDimensions::OneDimensional,
+            1 => DescriptorImageDescDimensions::TwoDimensional,
+            2 => DescriptorImageDescDimensions::ThreeDimensional,
+            3 => DescriptorImageDescDimensions::Cube,
+            _ => return None,
+        },
+        format: read_format(r)?,
+        multisampled: r.bool()?,
+        array_layers: read_array(r)?,
+    })
+}
+
+fn write_array(w: &mut Writer, array: &DescriptorImageDescArray) {
+    match array {
+        DescriptorImageDescArray::NonArrayed => w.u8(0),
+        DescriptorImageDescArray::Arrayed { max_layers: None } => w.u8(1),
+        DescriptorImageDescArray::Arrayed {
+            max_layers: Some(max_layers),
+        } => {
+            w.u8(2);
+            w.u32(*max_layers);
+        }
+    }
+}
+
+fn read_array(r: &mut Reader) -> Option<DescriptorImageDescArray> {
+    match r.u8()? {
+        0 => Some(DescriptorImageDescArray::NonArrayed),
+        1 => Some(DescriptorImageDescArray::Arrayed { max_layers: None }),
+        2 => Some(DescriptorImageDescArray::Arrayed {
+            max_layers: Some(r.u32()?),
+        }),
+        _ => None,
+    }
+}
+
+/// A format is stored as its index in `FORMATS`, or 255 for none.
+/// `None` if the format isn't in `FORMATS`.
+fn write_format(w: &mut Writer, format: Option<Format>) -> Option<()> {
+    match format {
+        Some(format) => w.u8(FORMATS.iter().position(|f| *f == format)? as u8),
+        None => w.u8(u8::MAX),
+    }
+    Some(())
+}
+
+/// The outer `Option` is `None` if the index is out of range.
+fn read_format(r: &mut Reader) -> Option<Option<Format>> {
+    match r.u8()? {
+        u8::MAX => Some(None),
+        i => FORMATS.get(i as usize).map(|f| Some(*f)),
+    }
+}
+
+fn write_stages(w: &mut Writer, stages: &ShaderStages) {
+    for stage in &[
+        stages.vertex,
+        stages.tessellation_control,
+        stages.tessellation_evaluation,
+        stages.geometry,
+        stages.fragment,
+        stages.compute,
+    ] {
+        w.bool(*stage);
+    }
+}
+
+fn read_stages(r: &mut Reader) -> Option<ShaderStages> {
+    Some(ShaderStages {
+        vertex: r.bool()?,
+        tessellation_control: r.bool()?,
+        tessellation_evaluation: r.bool()?,
+        geometry: r.bool()?,
+        fragment: r.bool()?,
+        compute: r.bool()?,
+    })
+}
@@ -1,19 +1,51 @@
-use crate::error::CompileError;
+use crate::cache::CompileCache;
+use crate::config::CompileConfig;
+use crate::diagnostic::{self, Severity};
+use crate::error::{CompileError, Error};
+use crate::layouts::Entry;
+use crate::permutation::Defines;
+use crate::reflection;
+use crate::source::{FileSource, ShaderSource};
+use crate::spirv;
+use crate::CompiledShader;
 use shaderc::{IncludeType, ResolvedInclude};
 use shaderc::{ShaderKind, CompileOptions};
 use std::fs::File;
 use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::borrow::Cow;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
 
-pub fn compile<T>(path: T, include_path: Option<T>, shader_kind: ShaderKind, compiler_options: Option<CompileOptions>) -> Result<Vec<u32>, CompileError>
+pub fn compile<T>(path: T, include_path: Option<T>, shader_kind: ShaderKind, compiler_options: Option<CompileOptions>) -> Result<CompiledShader, CompileError>
     where
         T: AsRef<Path>,
 {
-    compile_with_options(&read_to_string(&path), include_path, shader_kind, compiler_options)
+    let src = read_to_string(&path)?;
+    // The shader's own path names it, so the include path is only searched.
+    // A file stands for the folder it is in.
+    let include_dirs = include_path
+        .iter()
+        .map(|p| p.as_ref())
+        .filter_map(|p| if p.is_dir() { Some(p) } else { p.parent() })
+        .filter(|p| !p.as_os_str().is_empty())
+        .map(Path::to_path_buf)
+        .collect::<Vec<_>>();
+    let mut compiler = shaderc::Compiler::new().ok_or(CompileError::CreateCompiler)?;
+    let files: Arc<dyn ShaderSource> = Arc::new(FileSource);
+    let session = Session {
+        source: &files,
+        entry_point: "main",
+        include_dirs: &include_dirs,
+        cache: None,
+    };
+    compile_source(&mut compiler, &session, &src, Some(path), shader_kind, compiler_options)
+        .map(|(shader, _)| shader)
 }
 
-pub fn compile_from_string<T>(input: &str, include_path: Option<T>, shader_kind: ShaderKind, compiler_options: Option<CompileOptions>) -> Result<Vec<u32>, CompileError>
+/// See `crate::load_from_string` for how `include_path` is used.
+pub fn compile_from_string<T>(input: &str, include_path: Option<T>, shader_kind: ShaderKind, compiler_options: Option<CompileOptions>) -> Result<CompiledShader, CompileError>
     where
         T: AsRef<Path>,
 {
@@ -21,26 +53,237 @@ pub fn compile_from_string<T>(input: &str, include_path: Option<T>, shader_kind:
 }
 
 pub fn compile_with_options<T>(src: &str, include_path: Option<T>, shader_kind: ShaderKind, options: Option<CompileOptions>)
-                               -> Result<Vec<u32>, CompileError>
+                               -> Result<CompiledShader, CompileError>
     where
         T: AsRef<Path>,
 {
-    // TODO Probably shouldn't create this every time.
+    // Use a `ShaderCompiler` to avoid creating this every time.
     let mut compiler = shaderc::Compiler::new().ok_or(CompileError::CreateCompiler)?;
+    let files: Arc<dyn ShaderSource> = Arc::new(FileSource);
+    let session = Session {
+        source: &files,
+        entry_point: "main",
+        include_dirs: &[],
+        cache: None,
+    };
+    compile_source(&mut compiler, &session, src, include_path, shader_kind, options)
+        .map(|(shader, _)| shader)
+}
+
+/// A compiler session that can be reused for many compiles.
+/// Creating the shaderc compiler is expensive so holding on to one of
+/// these is faster than calling `load` for every shader.
+/// Every compile uses the session's `CompileConfig`.
+/// Shaders and includes are read from the session's `ShaderSource`,
+/// which is the file system unless another one is given.
+pub struct ShaderCompiler {
+    compiler: shaderc::Compiler,
+    config: CompileConfig,
+    source: Arc<dyn ShaderSource>,
+}
+
+impl ShaderCompiler {
+    pub fn new() -> Result<Self, Error> {
+        ShaderCompiler::with_config(CompileConfig::default())
+    }
+
+    pub fn with_config(config: CompileConfig) -> Result<Self, Error> {
+        ShaderCompiler::with_source(config, Arc::new(FileSource))
+    }
+
+    pub fn with_source(config: CompileConfig, source: Arc<dyn ShaderSource>) -> Result<Self, Error> {
+        let compiler = shaderc::Compiler::new()
+            .ok_or(Error::Compile(CompileError::CreateCompiler))?;
+        Ok(ShaderCompiler { compiler, config, source })
+    }
+
+    pub fn source(&self) -> &Arc<dyn ShaderSource> {
+        &self.source
+    }
+
+    pub fn config(&self) -> &CompileConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: CompileConfig) {
+        self.config = config;
+    }
+
+    /// Compiles the shader at `input`.
+    /// Relative includes are resolved from the shader's directory.
+    pub fn load<T>(&mut self, input: T, shader_kind: ShaderKind) -> Result<CompiledShader, Error>
+        where
+            T: AsRef<Path>,
+    {
+        let path = input.as_ref();
+        let src = self.read(path)?;
+        self.load_from_string(&src, Some(path), shader_kind)
+    }
+
+    /// Like `crate::load_auto` but with this session.
+    pub fn load_auto<T>(&mut self, input: T) -> Result<(ShaderKind, CompiledShader), Error>
+        where
+            T: AsRef<Path>,
+    {
+        let shader_kind = crate::shader_kind_from_path(&input).unwrap_or(ShaderKind::InferFromSource);
+        let shader = self.load(input, shader_kind)?;
+        Ok((reflection::detected_kind(shader_kind, &shader)?, shader))
+    }
+
+    pub fn load_from_string<T>(&mut self, source: &str, include_path: Option<T>, shader_kind: ShaderKind)
+                               -> Result<CompiledShader, Error>
+        where
+            T: AsRef<Path>,
+    {
+        self.compile(source, include_path, shader_kind, &Defines::new())
+            .map(|(shader, _)| shader)
+    }
+
+    /// Compiles the shader at `input` and reflects it.
+    pub(crate) fn load_reflected<T>(&mut self, input: T, shader_kind: ShaderKind) -> Result<(CompiledShader, Entry), Error>
+        where
+            T: AsRef<Path>,
+    {
+        let path = input.as_ref();
+        let src = self.read(path)?;
+        self.load_with_defines(&src, Some(path), shader_kind, &Defines::new())
+    }
 
+    fn read(&self, path: &Path) -> Result<String, Error> {
+        self.source
+            .read(path)
+            .map_err(|e| Error::Compile(CompileError::Open(path.to_path_buf(), e)))
+    }
+
+    /// Compiles with `defines` on top of the macros in the config and reflects the result.
+    /// The entry stored in the cache is used on a hit.
+    pub(crate) fn load_with_defines<T>(&mut self, source: &str, include_path: Option<T>, shader_kind: ShaderKind, defines: &Defines)
+                                       -> Result<(CompiledShader, Entry), Error>
+        where
+            T: AsRef<Path>,
+    {
+        let (shader, cached) = self.compile(source, include_path, shader_kind, defines)?;
+        let kind = reflection::detected_kind(shader_kind, &shader)?;
+        let entry = match cached {
+            Some((cached_kind, entry)) if cached_kind == kind => entry,
+            _ => reflection::reflect(kind, &shader)?,
+        };
+        Ok((shader, entry))
+    }
+
+    /// Also returns the entry if the cache had one or it was reflected to store it.
+    fn compile<T>(&mut self, source: &str, include_path: Option<T>, shader_kind: ShaderKind, defines: &Defines)
+                  -> Result<(CompiledShader, Option<(ShaderKind, Entry)>), Error>
+        where
+            T: AsRef<Path>,
+    {
+        let mut options = self.config.build().map_err(Error::Compile)?;
+        for (name, value) in defines {
+            options.add_macro_definition(name, value.as_ref().map(|v| v.as_str()));
+        }
+        let settings = self.config.cache_key();
+        let session = Session {
+            source: &self.source,
+            entry_point: self.config.get_entry_point(),
+            include_dirs: self.config.include_dirs(),
+            cache: self.config.get_cache().map(|cache| (cache, settings.as_str())),
+        };
+        compile_source(&mut self.compiler, &session, source, include_path, shader_kind, Some(options))
+            .map_err(Error::Compile)
+    }
+
+    /// Compiles with shaderc options instead of the session's config.
+    /// The include directories and entry point of the config are still used
+    /// but the cache is not, because the options can't be part of its key.
+    pub fn load_with_options<T>(&mut self, source: &str, include_path: Option<T>, shader_kind: ShaderKind, options: CompileOptions)
+                                -> Result<CompiledShader, Error>
+        where
+            T: AsRef<Path>,
+    {
+        let session = Session {
+            source: &self.source,
+            entry_point: self.config.get_entry_point(),
+            include_dirs: self.config.include_dirs(),
+            cache: None,
+        };
+        compile_source(&mut self.compiler, &session, source, include_path, shader_kind, Some(options))
+            .map(|(shader, _)| shader)
+            .map_err(Error::Compile)
+    }
+
+    pub fn load_compute<T>(&mut self, compute: T) -> Result<CompiledShader, Error>
+        where
+            T: AsRef<Path>,
+    {
+        self.load(compute, ShaderKind::Compute)
+    }
+
+    pub fn load_compute_from_string<T>(&mut self, source: &str, include_path: Option<T>)
+                                       -> Result<CompiledShader, Error>
+        where
+            T: AsRef<Path>,
+    {
+        self.load_from_string(source, include_path, ShaderKind::Compute)
+    }
+
+    /// Loads a SPIR-V module from the session's source.
+    pub fn load_spirv<T>(&self, path: T) -> Result<CompiledShader, Error>
+        where
+            T: AsRef<Path>,
+    {
+        spirv::load_spirv_from(&*self.source, path.as_ref())
+    }
+}
+
+/// Everything a compile uses that comes from the session rather than the shader.
+struct Session<'a> {
+    /// Where includes are read from.
+    source: &'a Arc<dyn ShaderSource>,
+    entry_point: &'a str,
+    include_dirs: &'a [PathBuf],
+    /// The cache and the settings part of its key.
+    cache: Option<(&'a CompileCache, &'a str)>,
+}
+
+/// `file_name` names the source in diagnostics and relative includes
+/// are resolved from its folder.
+/// With a cache, the entry stored with the shader and the stage it
+/// was reflected as are returned too.
+fn compile_source<T>(compiler: &mut shaderc::Compiler, session: &Session, src: &str, file_name: Option<T>, shader_kind: ShaderKind, options: Option<CompileOptions>)
+                     -> Result<(CompiledShader, Option<(ShaderKind, Entry)>), CompileError>
+    where
+        T: AsRef<Path>,
+{
+    let Session { source: shader_source, entry_point, include_dirs, cache } = *session;
     let mut options = {
         match options {
-            None => CompileOptions::new().ok_or(CompileError::CreateCompiler).unwrap(),
+            None => CompileOptions::new().ok_or(CompileError::CreateCompiler)?,
             Some(option) => option,
         }
     };
 
-    let path = {
-        if let Some(path) = &include_path {
-            options.set_include_callback(|path, include_type, folder_path, depth| {
-                get_include(path, include_type, folder_path, depth)
+    // Every file resolved by the include callback, so callers can
+    // tell which files the result depends on.
+    let includes = Rc::new(RefCell::new(Vec::new()));
+
+    let name = {
+        if file_name.is_some() || !include_dirs.is_empty() {
+            let includes = includes.clone();
+            let include_dirs = include_dirs.to_vec();
+            let shader_source = shader_source.clone();
+            options.set_include_callback(move |path, include_type, folder_path, depth| {
+                let resolved = get_include(&*shader_source, path, include_type, folder_path, depth, &include_dirs)?;
+                let file = shader_source.canonicalize(Path::new(&resolved.resolved_name));
+                let mut includes = includes.borrow_mut();
+                if !includes.contains(&file) {
+                    includes.push(file);
+                }
+                Ok(resolved)
             });
-            path.as_ref().to_str().ok_or(CompileError::InvalidPath)?
+            match &file_name {
+                Some(name) => name.as_ref().to_str().ok_or(CompileError::InvalidPath)?,
+                None => "",
+            }
         } else {
             options.set_include_callback(|path, include_type, folder_path, depth| {
                 default_get_include(path, include_type, folder_path, depth)
@@ -49,27 +292,101 @@ pub fn compile_with_options<T>(src: &str, include_path: Option<T>, shader_kind:
         }
     };
 
+    // The preprocessed source has the macros applied and every include
+    // pasted in, so it stands in for all of the files in the cache key.
+    let cache_key = match cache {
+        Some((cache, settings)) => {
+            let preprocessed = compiler
+                .preprocess(src, name, entry_point, Some(&options))
+                .map_err(compile_error)?
+                .as_text();
+            let included = includes
+                .borrow()
+                .iter()
+                .map(|p| p.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+                .join("\n");
+            let stage = format!("{:?}", shader_kind);
+            // The name is part of the key so warnings on a hit
+            // point at this file and not a copy of it.
+            let key = cache.key(&[
+                name.as_bytes(),
+                preprocessed.as_bytes(),
+                included.as_bytes(),
+                stage.as_bytes(),
+                settings.as_bytes(),
+            ]);
+            if let Some(cached) = key.as_ref().and_then(|key| cache.get(key)) {
+                let shader = CompiledShader {
+                    spriv: cached.spirv,
+                    includes: includes.borrow().clone(),
+                    warnings: diagnostic::parse(&cached.warnings, Severity::Warning),
+                    entry_point: entry_point.to_string(),
+                };
+                return Ok((shader, cached.entry));
+            }
+            key.map(|key| (cache, key))
+        }
+        None => None,
+    };
+
     let result = compiler
         .compile_into_spirv(
             src,
             shader_kind,
-            path,
-            "main",
+            name,
+            entry_point,
             Some(&options),
         )
-        .map_err(CompileError::Compile)?;
+        .map_err(compile_error)?;
     let data = result.as_binary();
-    Ok(data.to_owned())
+    let includes = includes.borrow().clone();
+    let warnings = if result.get_num_warnings() > 0 {
+        result.get_warning_messages()
+    } else {
+        String::new()
+    };
+    let shader = CompiledShader {
+        spriv: data.to_owned(),
+        includes,
+        warnings: diagnostic::parse(&warnings, Severity::Warning),
+        entry_point: entry_point.to_string(),
+    };
+    let entry = match cache_key {
+        Some((cache, key)) => {
+            // A module that can't be reflected is still cached so
+            // reflecting it reports the error again on a hit.
+            let entry = reflection::detected_kind(shader_kind, &shader)
+                .and_then(|kind| Ok((kind, reflection::reflect(kind, &shader)?)))
+                .ok();
+            cache.insert(&key, data, &warnings, entry.as_ref());
+            entry
+        }
+        None => None,
+    };
+    Ok((shader, entry))
+}
+
+/// Turns shaderc's compile output into diagnostics.
+/// Other failures don't have positions so they are kept as they are.
+fn compile_error(e: shaderc::Error) -> CompileError {
+    match e {
+        shaderc::Error::CompilationError(_, output) => {
+            CompileError::Diagnostics(diagnostic::parse(&output, Severity::Error))
+        }
+        e => CompileError::Compile(e),
+    }
 }
 
-pub fn read_to_string<'a, T>(path: &T) -> Cow<'a, str>
+pub fn read_to_string<'a, T>(path: &T) -> Result<Cow<'a, str>, CompileError>
     where
         T: AsRef<Path>,
 {
-    let mut f = File::open(path).map_err(CompileError::Open).expect("");
+    let open = |e| CompileError::Open(path.as_ref().to_path_buf(), e);
+    let mut f = File::open(path).map_err(open)?;
     let mut src = String::new();
-    f.read_to_string(&mut src).map_err(CompileError::Open).expect("");
-    Cow::Owned(src)
+    f.read_to_string(&mut src).map_err(open)?;
+    Ok(Cow::Owned(src))
 }
 
 fn default_get_include(
@@ -83,37 +400,27 @@ fn default_get_include(
 }
 
 fn get_include(
+    shader_source: &dyn ShaderSource,
     path: &str,
     include_type: IncludeType,
     folder_path: &str,
     _depth: usize,
+    include_dirs: &[PathBuf],
 ) -> Result<ResolvedInclude, String> {
-    match include_type {
-        IncludeType::Relative => {
-            let p = Path::new(path);
-            let mut folder = PathBuf::from(folder_path);
-            folder.pop();
-            folder.push(p);
-            let p = folder;
-            if !p.is_file() {
-                return Err("Include doesn't point to file".to_string());
-            }
-
-            let resolved_name = p
-                .to_str()
-                .ok_or("Path has invalid characters".to_string())?
-                .to_owned();
-            let p = p.canonicalize().map_err(|_| "Failed to parse include path".to_string())?;
-            let mut content = String::new();
-            File::open(p)
-                .map_err(|_| "Couldn't open include directory".to_string())?
-                .read_to_string(&mut content)
-                .map_err(|_| "Failed to read included shader".to_string())?;
-            Ok(ResolvedInclude {
-                resolved_name,
-                content,
-            })
-        }
-        IncludeType::Standard => Err("Standard includes are unimplemented".to_string()),
-    }
+    let relative_to = match include_type {
+        IncludeType::Relative => Some(Path::new(folder_path)),
+        IncludeType::Standard => None,
+    };
+    let file = shader_source.resolve_include(path, relative_to, include_dirs)?;
+    let resolved_name = file
+        .to_str()
+        .ok_or("Path has invalid characters".to_string())?
+        .to_owned();
+    let content = shader_source
+        .read(&file)
+        .map_err(|e| format!("Failed to read included shader {}: {}", resolved_name, e))?;
+    Ok(ResolvedInclude {
+        resolved_name,
+        content,
+    })
 }
@@ -0,0 +1,252 @@
+use crate::cache::CompileCache;
+use crate::error::CompileError;
+use shaderc::{CompileOptions, OptimizationLevel, ResourceKind, SourceLanguage, TargetEnv};
+use std::path::{Path, PathBuf};
+
+/// Settings used to compile shaders.
+/// Unlike `CompileOptions` this can be cloned and sent between threads
+/// so the options can be rebuilt for every compile.
+#[derive(Clone, Debug)]
+pub struct CompileConfig {
+    include_dirs: Vec<PathBuf>,
+    macros: Vec<(String, Option<String>)>,
+    optimization: Option<OptimizationLevel>,
+    target_env: Option<TargetEnv>,
+    target: Option<Target>,
+    warnings_as_errors: bool,
+    entry_point: String,
+    source_language: Option<SourceLanguage>,
+    binding_bases: Vec<(ResourceKind, u32)>,
+    auto_bind_uniforms: bool,
+    hlsl_io_mapping: bool,
+    hlsl_offsets: bool,
+    cache: Option<CompileCache>,
+}
+
+impl Default for CompileConfig {
+    fn default() -> Self {
+        CompileConfig {
+            include_dirs: Vec::new(),
+            macros: Vec::new(),
+            optimization: None,
+            target_env: None,
+            target: None,
+            warnings_as_errors: false,
+            entry_point: "main".to_string(),
+            source_language: None,
+            binding_bases: Vec::new(),
+            auto_bind_uniforms: false,
+            hlsl_io_mapping: false,
+            hlsl_offsets: false,
+            cache: None,
+        }
+    }
+}
+
+impl CompileConfig {
+    pub fn new() -> Self {
+        CompileConfig::default()
+    }
+
+    /// The function to compile. Defaults to `main`.
+    /// Reflection also uses this entry point.
+    pub fn entry_point(mut self, name: &str) -> Self {
+        self.entry_point = name.to_string();
+        self
+    }
+
+    /// Defaults to GLSL.
+    pub fn source_language(mut self, language: SourceLanguage) -> Self {
+        self.source_language = Some(language);
+        self
+    }
+
+    /// Shifts the bindings of every HLSL register of `kind` by `base`.
+    /// For example a base of 8 for `ResourceKind::Texture` puts `t0` at binding 8.
+    pub fn binding_base(mut self, kind: ResourceKind, base: u32) -> Self {
+        self.binding_bases.push((kind, base));
+        self
+    }
+
+    /// Gives uniforms without a binding one automatically.
+    pub fn auto_bind_uniforms(mut self) -> Self {
+        self.auto_bind_uniforms = true;
+        self
+    }
+
+    /// Uses HLSL rules to assign locations to inputs and outputs.
+    pub fn hlsl_io_mapping(mut self) -> Self {
+        self.hlsl_io_mapping = true;
+        self
+    }
+
+    /// Uses HLSL packing rules for the offsets of buffer members.
+    pub fn hlsl_offsets(mut self) -> Self {
+        self.hlsl_offsets = true;
+        self
+    }
+
+    /// Adds a directory to search for included files.
+    /// Directories are searched in the order they are added.
+    pub fn include_dir<T>(mut self, dir: T) -> Self
+    where
+        T: AsRef<Path>,
+    {
+        self.include_dirs.push(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Defines a macro as if by `#define name value`.
+    pub fn define(mut self, name: &str, value: Option<&str>) -> Self {
+        self.macros
+            .push((name.to_string(), value.map(|v| v.to_string())));
+        self
+    }
+
+    pub fn optimization(mut self, level: OptimizationLevel) -> Self {
+        self.optimization = Some(level);
+        self
+    }
+
+    pub fn target_env(mut self, env: TargetEnv) -> Self {
+        self.target_env = Some(env);
+        self
+    }
+
+    /// The Vulkan version to compile for.
+    /// This takes the place of `target_env`.
+    pub fn target(mut self, target: Target) -> Self {
+        self.target = Some(target);
+        self
+    }
+
+    /// Fails the compile if there are any warnings.
+    /// Useful for checking shaders in CI.
+    pub fn warnings_as_errors(mut self) -> Self {
+        self.warnings_as_errors = true;
+        self
+    }
+
+    /// Reuses SPIR-V from earlier runs when nothing it depends on has changed.
+    pub fn cache(mut self, cache: CompileCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn include_dirs(&self) -> &[PathBuf] {
+        &self.include_dirs
+    }
+
+    pub fn macros(&self) -> &[(String, Option<String>)] {
+        &self.macros
+    }
+
+    pub fn get_entry_point(&self) -> &str {
+        &self.entry_point
+    }
+
+    pub fn get_cache(&self) -> Option<&CompileCache> {
+        self.cache.as_ref()
+    }
+
+    /// The settings that change the output of a compile, for the cache key.
+    /// Include directories only change which files are included
+    /// and those are part of the key already.
+    pub(crate) fn cache_key(&self) -> String {
+        format!(
+            "{:?} {:?} {:?} {:?} {} {} {:?} {:?} {} {} {}",
+            self.macros,
+            self.optimization,
+            self.target_env,
+            self.target,
+            self.warnings_as_errors,
+            self.entry_point,
+            self.source_language,
+            self.binding_bases,
+            self.auto_bind_uniforms,
+            self.hlsl_io_mapping,
+            self.hlsl_offsets,
+        )
+    }
+
+    /// Creates the shaderc options for these settings.
+    /// Include directories are resolved by the compiler itself
+    /// so they are not part of the options.
+    pub fn build(&self) -> Result<CompileOptions<'static>, CompileError> {
+        let mut options = CompileOptions::new().ok_or(CompileError::CreateCompiler)?;
+        for (name, value) in &self.macros {
+            options.add_macro_definition(name, value.as_ref().map(|v| v.as_str()));
+        }
+        if let Some(level) = self.optimization {
+            options.set_optimization_level(level);
+        }
+        match (self.target, self.target_env) {
+            (Some(target), _) => options.set_target_env(TargetEnv::Vulkan, target.env_version()),
+            (None, Some(env)) => options.set_target_env(env, 0),
+            (None, None) => (),
+        }
+        if self.warnings_as_errors {
+            options.set_warnings_as_errors();
+        }
+        if let Some(language) = self.source_language {
+            options.set_source_language(language);
+        }
+        for (kind, base) in &self.binding_bases {
+            options.set_binding_base(*kind, *base);
+        }
+        if self.auto_bind_uniforms {
+            options.set_auto_bind_uniforms(true);
+        }
+        if self.hlsl_io_mapping {
+            options.set_hlsl_io_mapping(true);
+        }
+        if self.hlsl_offsets {
+            options.set_hlsl_offsets(true);
+        }
+        Ok(options)
+    }
+}
+
+/// The Vulkan version shaders are compiled for.
+/// Each version raises the SPIR-V version of the output:
+///
+/// | Target      | SPIR-V |
+/// |-------------|--------|
+/// | `Vulkan1_0` | 1.0    |
+/// | `Vulkan1_1` | 1.3    |
+/// | `Vulkan1_2` | 1.5    |
+///
+/// Reflection reads the same descriptors, push constants and
+/// interfaces for every target. Storage buffers are `BufferBlock`
+/// uniforms in SPIR-V 1.0 and use the `StorageBuffer` storage class
+/// from 1.3 on, and both are reflected as storage buffers.
+/// Acceleration structures are not reflected for any target.
+/// `Vulkan1_2` needs a shaderc that was built with Vulkan 1.2 support.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    Vulkan1_0,
+    Vulkan1_1,
+    Vulkan1_2,
+}
+
+impl Target {
+    /// The `(major, minor)` SPIR-V version of shaders compiled for this target.
+    pub fn spirv_version(self) -> (u32, u32) {
+        match self {
+            Target::Vulkan1_0 => (1, 0),
+            Target::Vulkan1_1 => (1, 3),
+            Target::Vulkan1_2 => (1, 5),
+        }
+    }
+
+    /// The version number shaderc expects with `TargetEnv::Vulkan`.
+    /// It is encoded like `VK_MAKE_VERSION`.
+    fn env_version(self) -> u32 {
+        let minor = match self {
+            Target::Vulkan1_0 => 0,
+            Target::Vulkan1_1 => 1,
+            Target::Vulkan1_2 => 2,
+        };
+        (1 << 22) | (minor << 12)
+    }
+}
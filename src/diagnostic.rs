@@ -0,0 +1,135 @@
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+/// A single message from the shader compiler.
+/// Errors in included files point at the included file
+/// because shaderc reports them with the include's resolved name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub file: Option<PathBuf>,
+    pub line: Option<u32>,
+    pub column: Option<u32>,
+    pub severity: Severity,
+    pub message: String,
+}
+
+const SEVERITIES: [(&str, Severity); 3] = [
+    ("error: ", Severity::Error),
+    ("warning: ", Severity::Warning),
+    ("note: ", Severity::Note),
+];
+
+/// Parses shaderc's output into diagnostics.
+/// Messages look like `file:line: error: message` or
+/// `file:line:column: warning: message` for HLSL.
+/// Output that can't be parsed becomes a single diagnostic
+/// without a location so nothing is lost.
+pub fn parse(output: &str, default_severity: Severity) -> Vec<Diagnostic> {
+    let mut diagnostics = output.lines().filter_map(parse_line).collect::<Vec<_>>();
+    let output = output.trim();
+    if diagnostics.is_empty() && !output.is_empty() {
+        diagnostics.push(Diagnostic {
+            file: None,
+            line: None,
+            column: None,
+            severity: default_severity,
+            message: output.to_string(),
+        });
+    }
+    diagnostics
+}
+
+fn parse_line(line: &str) -> Option<Diagnostic> {
+    SEVERITIES.iter().find_map(|(marker, severity)| {
+        let (location, message) = if let Some(message) = line.strip_prefix(marker) {
+            ("", message)
+        } else {
+            let i = line.find(&format!(": {}", marker))?;
+            (&line[..i], &line[i + 2 + marker.len()..])
+        };
+        let (file, line, column) = split_location(location);
+        Some(Diagnostic {
+            file,
+            line,
+            column,
+            severity: *severity,
+            message: message.trim().to_string(),
+        })
+    })
+}
+
+/// Splits `file:line:column` where the line and column are optional.
+/// Only numbers are taken off the end so Windows drive letters survive.
+fn split_location(location: &str) -> (Option<PathBuf>, Option<u32>, Option<u32>) {
+    let mut rest = location;
+    let mut numbers = Vec::new();
+    while numbers.len() < 2 {
+        let i = match rest.rfind(':') {
+            Some(i) => i,
+            None => break,
+        };
+        match rest[i + 1..].trim().parse::<u32>() {
+            Ok(n) => {
+                numbers.push(n);
+                rest = &rest[..i];
+            }
+            Err(_) => break,
+        }
+    }
+    let (line, column) = match numbers.as_slice() {
+        [line] => (Some(*line), None),
+        [column, line] => (Some(*line), Some(*column)),
+        _ => (None, None),
+    };
+    let file = if rest.is_empty() {
+        None
+    } else {
+        Some(PathBuf::from(rest))
+    };
+    (file, line, column)
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+/// Renders like rustc:
+///
+/// ```text
+/// error: 'foo' : undeclared identifier
+///   --> shaders/frag.glsl:5
+/// ```
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.severity, self.message)?;
+        if self.file.is_none() && self.line.is_none() {
+            return Ok(());
+        }
+        let file = self
+            .file
+            .as_ref()
+            .map(|file| file.display().to_string())
+            .unwrap_or_else(|| "<source>".to_string());
+        write!(f, "\n  --> {}", file)?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        if let Some(column) = self.column {
+            write!(f, ":{}", column)?;
+        }
+        Ok(())
+    }
+}
@@ -1,9 +1,15 @@
+use crate::diagnostic::Diagnostic;
+use std::fmt;
+use std::path::PathBuf;
+
 #[derive(Debug)]
 pub enum Error {
     Compile(CompileError),
     Layout(ConvertError),
     LoadingData(String),
     FileWatch(notify::Error),
+    InvalidPipeline(String),
+    Spirv(SpirvError),
 }
 
 #[derive(Debug)]
@@ -11,10 +17,76 @@ pub enum ConvertError {
     Unimplemented(String),
 }
 
+#[derive(Debug)]
+pub enum SpirvError {
+    /// The length in bytes isn't a whole number of words or is too short for the header.
+    Length(usize),
+    /// The first word isn't the SPIR-V magic number in either byte order.
+    Magic(u32),
+}
+
 #[derive(Debug)]
 pub enum CompileError {
     Compile(shaderc::Error),
-    Open(std::io::Error),
+    /// The shader failed to compile. Holds every message shaderc reported.
+    Diagnostics(Vec<Diagnostic>),
+    /// The file couldn't be read. Holds the path of the file.
+    Open(PathBuf, std::io::Error),
     InvalidPath,
     CreateCompiler,
 }
+
+impl Error {
+    /// The compiler messages if this is a failed compile.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        match self {
+            Error::Compile(CompileError::Diagnostics(diagnostics)) => diagnostics,
+            _ => &[],
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Compile(e) => write!(f, "{}", e),
+            Error::Layout(e) => write!(f, "{}", e),
+            Error::LoadingData(e) => write!(f, "failed to load shader data: {}", e),
+            Error::FileWatch(e) => write!(f, "file watch error: {}", e),
+            Error::InvalidPipeline(e) => write!(f, "invalid pipeline: {}", e),
+            Error::Spirv(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConvertError::Unimplemented(e) => write!(f, "unimplemented conversion: {}", e),
+        }
+    }
+}
+
+impl fmt::Display for SpirvError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpirvError::Length(len) => write!(f, "invalid SPIR-V length of {} bytes", len),
+            SpirvError::Magic(magic) => write!(f, "invalid SPIR-V magic number {:#010x}", magic),
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::Compile(e) => write!(f, "{}", e),
+            CompileError::Diagnostics(diagnostics) => {
+                let lines = diagnostics.iter().map(|d| d.to_string()).collect::<Vec<_>>();
+                write!(f, "{}", lines.join("\n"))
+            }
+            CompileError::Open(path, e) => write!(f, "failed to open {}: {}", path.display(), e),
+            CompileError::InvalidPath => write!(f, "path has invalid characters"),
+            CompileError::CreateCompiler => write!(f, "failed to create the shader compiler"),
+        }
+    }
+}
@@ -1,74 +1,168 @@
+mod batch;
+mod cache;
 mod compiler;
+mod config;
+mod diagnostic;
 pub mod error;
 mod reflection;
+mod source;
+mod spirv;
 mod srvk;
 pub mod layouts;
+mod library;
+mod permutation;
 mod watch;
 
 pub use layouts::*;
+pub use library::{LibraryBuilder, LibraryShader, ShaderLibrary};
+pub use permutation::{compile_permutations, Defines, Permutation, Permutations};
 pub use reflection::LayoutData;
-pub use watch::{Message, Watch};
+pub use source::{ChangeCallback, FileSource, MemorySource, ShaderSource};
+pub use spirv::{load_spirv, load_spirv_bytes};
+pub use batch::{compile_batch, compile_batch_with_threads, BatchInput, BatchJob, BatchResult, JobResult};
+pub use cache::{CacheStats, CompileCache};
+pub use compiler::ShaderCompiler;
+pub use config::{CompileConfig, Target};
+pub use diagnostic::{Diagnostic, Severity};
+pub use watch::{Message, ReloadCallback, Watch, WatchBuilder, WatchMode, WatchState};
+#[cfg(feature = "stream")]
+pub use watch::WatchStream;
 pub use error::*;
 
 use shaderc::CompileOptions;
 use spirv_reflect as sr;
 use vulkano as vk;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use shaderc::ShaderKind;
 use std::borrow::Borrow;
 
+/// The compiled stages of a pipeline.
 #[derive(Clone)]
 pub struct CompiledShaders {
-    pub vertex: Vec<u32>,
-    pub fragment: Vec<u32>,
-    pub compute: Vec<u32>,
+    pub stages: Vec<(ShaderKind, CompiledShader)>,
+}
+
+impl CompiledShaders {
+    /// The compiled shader for `kind` if the pipeline has that stage.
+    pub fn get(&self, kind: ShaderKind) -> Option<&CompiledShader> {
+        self.stages
+            .iter()
+            .find(|(k, _)| *k == kind)
+            .map(|(_, shader)| shader)
+    }
+
+    pub fn vertex(&self) -> Option<&CompiledShader> {
+        self.get(ShaderKind::Vertex)
+    }
+
+    pub fn fragment(&self) -> Option<&CompiledShader> {
+        self.get(ShaderKind::Fragment)
+    }
+
+    pub fn compute(&self) -> Option<&CompiledShader> {
+        self.get(ShaderKind::Compute)
+    }
 }
 
 #[derive(Clone)]
 pub struct CompiledShader {
     pub spriv: Vec<u32>,
+    /// Every file pulled in through `#include` while compiling.
+    pub includes: Vec<PathBuf>,
+    /// Warnings from the compile. Empty if there were none.
+    pub warnings: Vec<Diagnostic>,
+    /// The name of the function the shader was compiled from.
+    pub entry_point: String,
 }
 
+impl CompiledShader {
+    /// The `(major, minor)` SPIR-V version from the module header.
+    pub fn spirv_version(&self) -> Option<(u32, u32)> {
+        let version = self.spriv.get(1)?;
+        Some(((version >> 16) & 0xff, (version >> 8) & 0xff))
+    }
+}
+
+/// Compiles the shader at `input`.
+/// Relative includes are resolved from the shader's folder and then from
+/// `include_path`, which can be a folder or a file in the folder.
 pub fn load<T>(input: T, include_path: Option<T>, shader_kind: ShaderKind, compiler_options: Option<CompileOptions>)
                -> Result<CompiledShader, Error>
     where
         T: AsRef<Path>,
 {
-    Ok(CompiledShader {
-        spriv: compiler::compile(input, include_path, shader_kind, compiler_options).map_err(Error::Compile)?
-    })
+    compiler::compile(input, include_path, shader_kind, compiler_options).map_err(Error::Compile)
 }
 
+/// `include_path` is used as the file name of `source`.
+/// It appears in diagnostics and relative includes are resolved from its folder.
 pub fn load_from_string<T>(source: &str, include_path: Option<T>, shader_kind: ShaderKind, compiler_options: Option<CompileOptions>) -> Result<CompiledShader, Error>
     where
         T: AsRef<Path>,
 {
-    Ok(CompiledShader {
-        spriv: compiler::compile_from_string(source, include_path, shader_kind, compiler_options).map_err(Error::Compile)?
-    })
+    compiler::compile_from_string(source, include_path, shader_kind, compiler_options).map_err(Error::Compile)
+}
+
+/// Compiles the shader at `input` and returns the stage it was compiled as.
+/// The stage comes from the file extension and otherwise from a
+/// `#pragma shader_stage(...)` in the source.
+pub fn load_auto<T>(input: T, compiler_options: Option<CompileOptions>) -> Result<(ShaderKind, CompiledShader), Error>
+    where
+        T: AsRef<Path>,
+{
+    let shader_kind = shader_kind_from_path(&input).unwrap_or(ShaderKind::InferFromSource);
+    let shader = load_from_string(&compiler::read_to_string(&input).map_err(Error::Compile)?, Some(&input), shader_kind, compiler_options)?;
+    Ok((reflection::detected_kind(shader_kind, &shader)?, shader))
 }
 
 pub fn load_compute<T>(compute: T, compiler_options: Option<CompileOptions>) -> Result<CompiledShader, Error>
     where
         T: AsRef<Path>,
 {
-    load_compute_from_string(&compiler::read_to_string(&compute), Some(compute), compiler_options)
+    load_compute_from_string(&compiler::read_to_string(&compute).map_err(Error::Compile)?, Some(compute), compiler_options)
 }
 
 pub fn load_compute_from_string<T>(source : &str, include_path : Option<T>, compiler_options: Option<CompileOptions>) -> Result<CompiledShader, Error>
     where
         T: AsRef<Path>,
 {
-    Ok(CompiledShader {
-        spriv: compiler::compile_from_string(source, include_path, ShaderKind::Compute, compiler_options).map_err(Error::Compile)?
-    })
+    compiler::compile_from_string(source, include_path, ShaderKind::Compute, compiler_options).map_err(Error::Compile)
+}
+
+/// The stage of a shader from its file extension.
+/// `bloom.frag`, `bloom.frag.glsl` and `bloom.frag.spv` are all fragment shaders.
+pub fn shader_kind_from_path<T>(path: T) -> Option<ShaderKind>
+    where
+        T: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let kind = |ext: &std::ffi::OsStr| match ext.to_str()? {
+        "vert" => Some(ShaderKind::Vertex),
+        "frag" => Some(ShaderKind::Fragment),
+        "comp" => Some(ShaderKind::Compute),
+        "geom" => Some(ShaderKind::Geometry),
+        "tesc" => Some(ShaderKind::TessControl),
+        "tese" => Some(ShaderKind::TessEvaluation),
+        "mesh" => Some(ShaderKind::Mesh),
+        "task" => Some(ShaderKind::Task),
+        _ => None,
+    };
+    match path.extension().and_then(kind) {
+        Some(k) => Some(k),
+        None if path.extension().is_some_and(|ext| ext == "glsl" || ext == "spv") => path
+            .file_stem()
+            .map(Path::new)
+            .and_then(|stem| stem.extension())
+            .and_then(kind),
+        None => None,
+    }
 }
 
 pub fn parse_compute(code: &CompiledShader) -> Result<Entry, Error> {
-    reflection::create_compute_entry(&code.spriv)
+    reflection::create_compute_entry(&code.spriv, &code.entry_point)
 }
 
 /// Parses the shaders and gives an entry point
 pub fn parse(code: &CompiledShader) -> Result<Entry, Error> {
-    reflection::create_entry(&code.spriv)
+    reflection::create_entry(&code.spriv, &code.entry_point)
 }
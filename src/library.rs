@@ -0,0 +1,351 @@
+use crate::compiler::ShaderCompiler;
+use crate::config::CompileConfig;
+use crate::error::{CompileError, Error};
+use crate::layouts::Entry;
+use crate::source::{FileSource, ShaderSource};
+use crate::watch::{self, Backend, Handler, WatchMode};
+use crate::CompiledShader;
+use notify::{DebouncedEvent, RecursiveMode};
+use shaderc::ShaderKind;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// A compiled shader in a `ShaderLibrary`.
+#[derive(Clone)]
+pub struct LibraryShader {
+    pub kind: ShaderKind,
+    pub shader: CompiledShader,
+    pub entry: Entry,
+}
+
+/// Watches a whole directory tree and keeps every shader in it compiled
+/// using a single watcher and thread.
+/// The stage of each shader comes from its file extension.
+/// Shaders are named by their path relative to the root with `/`
+/// as the separator, for example `post/bloom.frag`.
+pub struct ShaderLibrary {
+    _handler: Handler,
+    /// The name of each shader that was compiled and the result.
+    pub rx: Receiver<(String, Result<LibraryShader, Error>)>,
+    shaders: Arc<Mutex<HashMap<String, LibraryShader>>>,
+}
+
+struct Library {
+    root: PathBuf,
+    source: Arc<dyn ShaderSource>,
+    /// Whether changes come from the file watcher rather than the source.
+    watches_files: bool,
+    shaders: Arc<Mutex<HashMap<String, LibraryShader>>>,
+    /// Files included by each shader in its last successful compile.
+    includes: HashMap<PathBuf, Vec<PathBuf>>,
+    /// Entry points that override the config, by shader name.
+    entry_points: HashMap<String, String>,
+    shutdown: Arc<AtomicBool>,
+    tx: Sender<(String, Result<LibraryShader, Error>)>,
+}
+
+/// Builds a `ShaderLibrary`.
+pub struct LibraryBuilder {
+    root: PathBuf,
+    config: CompileConfig,
+    frequency: Duration,
+    mode: WatchMode,
+    entry_points: HashMap<String, String>,
+    source: Arc<dyn ShaderSource>,
+}
+
+impl LibraryBuilder {
+    /// Watches every shader under `root`.
+    pub fn new<T>(root: T) -> Self
+    where
+        T: AsRef<Path>,
+    {
+        LibraryBuilder {
+            root: root.as_ref().to_path_buf(),
+            config: CompileConfig::default(),
+            frequency: watch::DEFAULT_FREQUENCY,
+            mode: WatchMode::Auto,
+            entry_points: HashMap::new(),
+            source: Arc::new(FileSource),
+        }
+    }
+
+    /// The settings every shader is compiled with.
+    pub fn config(mut self, config: CompileConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// How often the watcher will check the directories.
+    pub fn frequency(mut self, frequency: Duration) -> Self {
+        self.frequency = frequency;
+        self
+    }
+
+    /// How changes are detected. Defaults to `WatchMode::Auto`.
+    pub fn mode(mut self, mode: WatchMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Compiles the shader called `name` with `entry_point`
+    /// instead of the entry point in the config.
+    pub fn entry_point(mut self, name: &str, entry_point: &str) -> Self {
+        self.entry_points
+            .insert(name.to_string(), entry_point.to_string());
+        self
+    }
+
+    /// Where the shaders and their includes are read from.
+    /// Defaults to the file system. If the source can report changes
+    /// it is used instead of watching the file system.
+    pub fn source(mut self, source: Arc<dyn ShaderSource>) -> Self {
+        self.source = source;
+        self
+    }
+
+    pub fn build(self) -> Result<ShaderLibrary, Error> {
+        let (notify_tx, notify_rx) = mpsc::channel();
+        let tx = notify_tx.clone();
+        let subscribed = self.source.subscribe(Box::new(move |path| {
+            tx.send(DebouncedEvent::Write(path.to_path_buf())).is_ok()
+        }));
+        let (root, watcher) = if subscribed {
+            (self.source.canonicalize(&self.root), None)
+        } else {
+            let root = self
+                .root
+                .canonicalize()
+                .map_err(|e| Error::Compile(CompileError::Open(self.root.clone(), e)))?;
+            let watcher = Backend::create(self.mode, notify_tx.clone(), self.frequency, |mut watcher| {
+                watcher
+                    .watch(&root, RecursiveMode::Recursive)
+                    .map(|_| watcher)
+                    .map_err(Error::FileWatch)
+            })?;
+            (root, Some(watcher))
+        };
+
+        let (tx, rx) = mpsc::channel();
+        let shaders = Arc::new(Mutex::new(HashMap::new()));
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let mut library = Library {
+            root,
+            source: self.source,
+            watches_files: !subscribed,
+            shaders: shaders.clone(),
+            includes: HashMap::new(),
+            entry_points: self.entry_points,
+            shutdown: shutdown.clone(),
+            tx,
+        };
+        let config = self.config;
+        thread::spawn(move || {
+            let _watcher = watcher;
+            let mut compiler = match ShaderCompiler::with_source(config, library.source.clone()) {
+                Ok(compiler) => compiler,
+                Err(e) => {
+                    library.send(String::new(), Err(e));
+                    return;
+                }
+            };
+            library.load_dir(&mut compiler, &library.root.clone());
+            for event in notify_rx.iter() {
+                if library.shutdown.load(Ordering::SeqCst) {
+                    break;
+                }
+                library.handle(&mut compiler, event);
+            }
+        });
+        Ok(ShaderLibrary {
+            _handler: Handler {
+                shutdown,
+                notify_tx,
+            },
+            rx,
+            shaders,
+        })
+    }
+}
+
+impl ShaderLibrary {
+    /// Watches every shader under `root`.
+    /// Frequency is how often the watcher will check the directories.
+    pub fn create<T>(root: T, config: CompileConfig, frequency: Duration) -> Result<Self, Error>
+    where
+        T: AsRef<Path>,
+    {
+        LibraryBuilder::new(root)
+            .config(config)
+            .frequency(frequency)
+            .build()
+    }
+
+    /// The latest good compile of the shader called `name`.
+    pub fn get(&self, name: &str) -> Option<LibraryShader> {
+        self.shaders
+            .lock()
+            .ok()
+            .and_then(|shaders| shaders.get(name).cloned())
+    }
+
+    /// The names of all the shaders that have compiled.
+    pub fn names(&self) -> Vec<String> {
+        self.shaders
+            .lock()
+            .map(|shaders| shaders.keys().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+impl Library {
+    fn name(&self, path: &Path) -> Option<String> {
+        path.strip_prefix(&self.root).ok().map(|p| {
+            p.components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/")
+        })
+    }
+
+    /// Makes `path` comparable with the paths of the shaders.
+    fn normalize(&self, path: &Path) -> PathBuf {
+        if self.watches_files {
+            watch::normalize(path)
+        } else {
+            self.source.canonicalize(path)
+        }
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        if self.watches_files {
+            path.exists()
+        } else {
+            self.source.exists(path)
+        }
+    }
+
+    fn send(&self, name: String, result: Result<LibraryShader, Error>) {
+        if !self.shutdown.load(Ordering::SeqCst) {
+            self.tx.send((name, result)).ok();
+        }
+    }
+
+    /// Compiles every shader under `dir`.
+    fn load_dir(&mut self, compiler: &mut ShaderCompiler, dir: &Path) {
+        for file in self.source.files(dir) {
+            self.load(compiler, &file);
+        }
+    }
+
+    /// Compiles the shader at `path`.
+    /// Files that aren't shaders are ignored.
+    fn load(&mut self, compiler: &mut ShaderCompiler, path: &Path) {
+        let (name, kind) = match (self.name(path), crate::shader_kind_from_path(path)) {
+            (Some(name), Some(kind)) => (name, kind),
+            _ => return,
+        };
+        let entry_point = self.entry_points.get(&name).map(String::as_str);
+        let result = watch::load_stage(compiler, path, kind, entry_point)
+            .map(|(shader, entry)| LibraryShader { kind, shader, entry });
+        if let Ok(shader) = &result {
+            self.includes
+                .insert(path.to_path_buf(), shader.shader.includes.clone());
+            if let Ok(mut shaders) = self.shaders.lock() {
+                shaders.insert(name.clone(), shader.clone());
+            }
+        }
+        self.send(name, result);
+    }
+
+    /// Recompiles `path` and every shader that includes it.
+    fn changed(&mut self, compiler: &mut ShaderCompiler, path: &Path) {
+        let path = self.normalize(path);
+        if self.watches_files && is_dir(&path) {
+            self.load_dir(compiler, &path);
+            return;
+        }
+        // Sources only report that a file changed, which includes removing it.
+        if !self.exists(&path) {
+            self.removed(&path);
+            return;
+        }
+        let dependents = self
+            .includes
+            .iter()
+            .filter(|(shader, includes)| **shader != path && includes.contains(&path))
+            .map(|(shader, _)| shader.clone())
+            .collect::<Vec<_>>();
+        self.load(compiler, &path);
+        for shader in dependents {
+            self.load(compiler, &shader);
+        }
+    }
+
+    /// Drops the shader at `path`, or every shader under it if it was a directory.
+    fn removed(&mut self, path: &Path) {
+        let path = self.normalize(path);
+        if self.exists(&path) {
+            return;
+        }
+        self.includes.retain(|shader, _| !shader.starts_with(&path));
+        let name = match self.name(&path) {
+            Some(name) => name,
+            None => return,
+        };
+        let prefix = format!("{}/", name);
+        let mut removed = self
+            .shaders
+            .lock()
+            .map(|mut shaders| {
+                let removed = shaders
+                    .keys()
+                    .filter(|n| **n == name || n.starts_with(&prefix) || name.is_empty())
+                    .cloned()
+                    .collect::<Vec<_>>();
+                for n in &removed {
+                    shaders.remove(n);
+                }
+                removed
+            })
+            .unwrap_or_default();
+        removed.sort();
+        for name in removed {
+            let e = io::Error::new(io::ErrorKind::NotFound, "the file was removed");
+            let file = self.root.join(&name);
+            self.send(name, Err(Error::Compile(CompileError::Open(file, e))));
+        }
+    }
+
+    fn handle(&mut self, compiler: &mut ShaderCompiler, event: DebouncedEvent) {
+        match event {
+            DebouncedEvent::Create(path) | DebouncedEvent::Write(path) => {
+                self.changed(compiler, &path)
+            }
+            DebouncedEvent::Remove(path) => self.removed(&path),
+            DebouncedEvent::Rename(from, to) => {
+                self.removed(&from);
+                self.changed(compiler, &to);
+            }
+            DebouncedEvent::Rescan => self.load_dir(compiler, &self.root.clone()),
+            DebouncedEvent::Error(e, path) => {
+                let name = path.and_then(|p| self.name(&p)).unwrap_or_default();
+                self.send(name, Err(Error::FileWatch(e)));
+            }
+            DebouncedEvent::Chmod(_)
+            | DebouncedEvent::NoticeWrite(_)
+            | DebouncedEvent::NoticeRemove(_) => (),
+        }
+    }
+}
+
+/// Whether `path` is a directory, without following symlinks.
+fn is_dir(path: &Path) -> bool {
+    std::fs::symlink_metadata(path).is_ok_and(|m| m.is_dir())
+}
@@ -0,0 +1,135 @@
+use crate::compiler::ShaderCompiler;
+use crate::error::Error;
+use crate::layouts::Entry;
+use crate::CompiledShader;
+use shaderc::ShaderKind;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The macros defined for one permutation.
+/// A name maps to its value, or `None` for a plain `#define NAME`.
+/// Macros that are left undefined are not in the map.
+pub type Defines = BTreeMap<String, Option<String>>;
+
+/// One compiled permutation.
+#[derive(Clone)]
+pub struct Permutation {
+    pub shader: CompiledShader,
+    pub entry: Entry,
+}
+
+#[derive(Clone, Debug)]
+enum Value {
+    Undefined,
+    Defined(Option<String>),
+}
+
+/// Compiles one shader under every combination of a set of macros.
+///
+/// ```ignore
+/// let permutations = Permutations::new()
+///     .flag("SHADOWS")
+///     .flag("SKINNING")
+///     .values("MSAA", &["1", "4"]);
+/// // 8 permutations, all compiled with one session.
+/// let shaders = permutations.compile_all(&mut compiler, source, None::<&Path>, ShaderKind::Fragment);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Permutations {
+    axes: Vec<(String, Vec<Value>)>,
+}
+
+impl Permutations {
+    pub fn new() -> Self {
+        Permutations::default()
+    }
+
+    /// A macro that is either undefined or defined without a value.
+    pub fn flag(mut self, name: &str) -> Self {
+        self.axes
+            .push((name.to_string(), vec![Value::Undefined, Value::Defined(None)]));
+        self
+    }
+
+    /// A macro that is always defined as one of `values`.
+    pub fn values(mut self, name: &str, values: &[&str]) -> Self {
+        let values = values
+            .iter()
+            .map(|v| Value::Defined(Some(v.to_string())))
+            .collect();
+        self.axes.push((name.to_string(), values));
+        self
+    }
+
+    /// Every combination of the macros.
+    pub fn combinations(&self) -> Vec<Defines> {
+        let mut combinations = vec![Defines::new()];
+        for (name, values) in &self.axes {
+            combinations = combinations
+                .into_iter()
+                .flat_map(|defines| {
+                    values.iter().map(move |value| {
+                        let mut defines = defines.clone();
+                        if let Value::Defined(value) = value {
+                            defines.insert(name.clone(), value.clone());
+                        }
+                        defines
+                    })
+                })
+                .collect();
+        }
+        combinations
+    }
+
+    /// Compiles every combination of the macros.
+    pub fn compile_all<T>(
+        &self,
+        compiler: &mut ShaderCompiler,
+        source: &str,
+        include_path: Option<T>,
+        shader_kind: ShaderKind,
+    ) -> BTreeMap<Defines, Result<Permutation, Error>>
+    where
+        T: AsRef<Path>,
+    {
+        compile_permutations(compiler, source, include_path, shader_kind, &self.combinations())
+    }
+}
+
+/// Compiles `source` once for each set of defines in `permutations`.
+/// Use this with a subset of `Permutations::combinations` to only
+/// compile the permutations that are needed.
+/// The defines are added on top of the macros in the compiler's config.
+/// A failed permutation doesn't stop the others from compiling.
+pub fn compile_permutations<T>(
+    compiler: &mut ShaderCompiler,
+    source: &str,
+    include_path: Option<T>,
+    shader_kind: ShaderKind,
+    permutations: &[Defines],
+) -> BTreeMap<Defines, Result<Permutation, Error>>
+where
+    T: AsRef<Path>,
+{
+    permutations
+        .iter()
+        .map(|defines| {
+            let result = compile_one(compiler, source, include_path.as_ref(), shader_kind, defines);
+            (defines.clone(), result)
+        })
+        .collect()
+}
+
+fn compile_one<T>(
+    compiler: &mut ShaderCompiler,
+    source: &str,
+    include_path: Option<T>,
+    shader_kind: ShaderKind,
+    defines: &Defines,
+) -> Result<Permutation, Error>
+where
+    T: AsRef<Path>,
+{
+    let (shader, entry) = compiler.load_with_defines(source, include_path, shader_kind, defines)?;
+    Ok(Permutation { shader, entry })
+}
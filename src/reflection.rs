@@ -6,6 +6,7 @@ use crate::vk::descriptor::descriptor::*;
 use crate::vk::descriptor::pipeline_layout::PipelineLayoutDescPcRange;
 use crate::vk::pipeline::shader::ShaderInterfaceDefEntry;
 use crate::CompiledShaders;
+use shaderc::ShaderKind;
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::convert::TryFrom;
@@ -24,10 +25,10 @@ pub struct LayoutData {
     pub pc_ranges: Vec<PipelineLayoutDescPcRange>,
 }
 
-pub fn create_entry(spirv: &Vec<u32>) -> Result<Entry, Error> {
+pub fn create_entry(spirv: &Vec<u32>, entry_point: &str) -> Result<Entry, Error> {
 
-    let vertex_interfaces = create_interfaces(spirv)?;
-    let vertex_layout = create_layouts(spirv)?;
+    let vertex_interfaces = create_interfaces(spirv, entry_point)?;
+    let vertex_layout = create_layouts(spirv, entry_point)?;
 
     let input = Some(Input {
         inputs: vertex_interfaces.inputs,
@@ -46,9 +47,9 @@ pub fn create_entry(spirv: &Vec<u32>) -> Result<Entry, Error> {
     })
 }
 
-pub fn create_compute_entry(spirv: &Vec<u32>) -> Result<Entry, Error> {
+pub fn create_compute_entry(spirv: &Vec<u32>, entry_point: &str) -> Result<Entry, Error> {
 
-    let compute_layout = create_layouts(spirv)?;
+    let compute_layout = create_layouts(spirv, entry_point)?;
 
     let layout = Layout {
         layout_data: compute_layout,
@@ -61,12 +62,98 @@ pub fn create_compute_entry(spirv: &Vec<u32>) -> Result<Entry, Error> {
     })
 }
 
-fn create_interfaces(data: &[u32]) -> Result<ShaderInterfaces, Error> {
+/// The stage a SPIR-V module was compiled for.
+pub fn shader_kind(spirv: &[u32]) -> Option<ShaderKind> {
+    use sr::types::ReflectShaderStageFlags as Stage;
+    let stage = sr::ShaderModule::load_u32_data(spirv).ok()?.get_shader_stage();
+    let kinds = [
+        (Stage::VERTEX, ShaderKind::Vertex),
+        (Stage::TESSELLATION_CONTROL, ShaderKind::TessControl),
+        (Stage::TESSELLATION_EVALUATION, ShaderKind::TessEvaluation),
+        (Stage::GEOMETRY, ShaderKind::Geometry),
+        (Stage::FRAGMENT, ShaderKind::Fragment),
+        (Stage::COMPUTE, ShaderKind::Compute),
+    ];
+    kinds
+        .iter()
+        .find(|(flag, _)| stage.contains(*flag))
+        .map(|(_, kind)| *kind)
+}
+
+/// The stage `shader` was compiled as.
+/// Shaders compiled with `InferFromSource` are checked with reflection.
+pub(crate) fn detected_kind(kind: ShaderKind, shader: &CompiledShader) -> Result<ShaderKind, Error> {
+    if kind != ShaderKind::InferFromSource {
+        return Ok(kind);
+    }
+    shader_kind(&shader.spriv)
+        .ok_or_else(|| Error::LoadingData("Couldn't detect the shader stage".to_string()))
+}
+
+/// Reflects the entry of a compiled `kind` stage.
+pub(crate) fn reflect(kind: ShaderKind, shader: &CompiledShader) -> Result<Entry, Error> {
+    match kind {
+        ShaderKind::Compute => crate::parse_compute(shader),
+        _ => crate::parse(shader),
+    }
+}
+
+/// The name of the first entry point in a SPIR-V module.
+pub fn entry_point_name(spirv: &[u32]) -> Option<String> {
+    let name = sr::ShaderModule::load_u32_data(spirv).ok()?.get_entry_point_name();
+    if name.is_empty() {
+        None
+    } else {
+        Some(name)
+    }
+}
+
+/// Combines the entries of several pipeline stages into one.
+/// The input is taken from the first stage, the output from the last
+/// and the descriptors and push constants of every stage are merged.
+pub fn merge_entries<'a, I>(entries: I) -> Entry
+where
+    I: IntoIterator<Item = &'a Entry>,
+{
+    let mut entries = entries.into_iter();
+    let mut merged = match entries.next() {
+        Some(entry) => entry.clone(),
+        None => return Entry::default(),
+    };
+    for entry in entries {
+        merged.output = entry.output.clone();
+        merge_layouts(&mut merged.layout.layout_data, &entry.layout.layout_data);
+    }
+    merged
+}
+
+fn merge_layouts(into: &mut LayoutData, from: &LayoutData) {
+    for (&set, bindings) in &from.descriptions {
+        let merged = into.descriptions.entry(set).or_insert_with(HashMap::new);
+        for (&binding, desc) in bindings {
+            merged.entry(binding).or_insert_with(|| desc.clone());
+        }
+        into.num_bindings.insert(set, merged.len());
+    }
+    into.num_sets = into.descriptions.len();
+    for range in &from.pc_ranges {
+        let exists = into
+            .pc_ranges
+            .iter()
+            .any(|r| r.offset == range.offset && r.size == range.size);
+        if !exists {
+            into.pc_ranges.push(*range);
+        }
+    }
+    into.num_constants = into.pc_ranges.len();
+}
+
+fn create_interfaces(data: &[u32], entry_point: &str) -> Result<ShaderInterfaces, Error> {
     sr::ShaderModule::load_u32_data(data)
         .map_err(|e| Error::LoadingData(e.to_string()))
         .map(|m| {
             let inputs = m
-                .enumerate_input_variables(None)
+                .enumerate_input_variables(Some(entry_point))
                 .map_err(|e| Error::LoadingData(e.to_string()))
                 .and_then(|inputs| {
                     inputs
@@ -83,7 +170,7 @@ fn create_interfaces(data: &[u32]) -> Result<ShaderInterfaces, Error> {
                         .collect::<Result<Vec<ShaderInterfaceDefEntry>, _>>()
                 });
             let outputs = m
-                .enumerate_output_variables(None)
+                .enumerate_output_variables(Some(entry_point))
                 .map_err(|e| Error::LoadingData(e.to_string()))
                 .and_then(|outputs| {
                     outputs
@@ -104,12 +191,12 @@ fn create_interfaces(data: &[u32]) -> Result<ShaderInterfaces, Error> {
     .and_then(|t| t)
 }
 
-fn create_layouts(data: &[u32]) -> Result<LayoutData, Error> {
+fn create_layouts(data: &[u32], entry_point: &str) -> Result<LayoutData, Error> {
     let mut ret = sr::ShaderModule::load_u32_data(data);
 
     ret.map(|m| {
             let descs: Result<_, Error> = m
-                .enumerate_descriptor_sets(None)
+                .enumerate_descriptor_sets(Some(entry_point))
                 .map_err(|e| Error::LoadingData(e.to_string()))
                 .and_then(|sets| {
                     let num_sets = sets.len();
@@ -147,7 +234,7 @@ fn create_layouts(data: &[u32]) -> Result<LayoutData, Error> {
                     descriptions.map(|d| (num_sets, num_bindings, d))
                 });
             let pcs = m
-                .enumerate_push_constant_blocks(None)
+                .enumerate_push_constant_blocks(Some(entry_point))
                 .map_err(|e| Error::LoadingData(e.to_string()))
                 .map(|constants| {
                     let num_constants = constants.len();
@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+
+/// Called with the path of each file that changed.
+/// Returning false unsubscribes the callback.
+pub type ChangeCallback = Box<dyn Fn(&Path) -> bool + Send>;
+
+/// Where shaders and the files they include are read from.
+pub trait ShaderSource: Send + Sync {
+    fn read_bytes(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Reads the file at `path` as UTF-8.
+    fn read(&self, path: &Path) -> io::Result<String> {
+        String::from_utf8(self.read_bytes(path)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// A unique name for `path` so the same file is always
+    /// recorded the same way in `CompiledShader::includes`.
+    fn canonicalize(&self, path: &Path) -> PathBuf {
+        path.to_path_buf()
+    }
+
+    /// Finds the file an `#include` refers to.
+    /// `relative_to` is the including file for `#include "name"`
+    /// and `None` for `#include <name>`.
+    /// Relative includes are looked up next to the including file first
+    /// and then fall back to the include directories like standard includes.
+    /// A source compiled without a file name has no folder, so its
+    /// relative includes only use the include directories.
+    fn resolve_include(
+        &self,
+        name: &str,
+        relative_to: Option<&Path>,
+        include_dirs: &[PathBuf],
+    ) -> Result<PathBuf, String> {
+        let mut searched = Vec::new();
+        let relative_to = relative_to.filter(|file| !file.as_os_str().is_empty());
+        if let Some(file) = relative_to {
+            let folder = file.parent().unwrap_or_else(|| Path::new(""));
+            let p = folder.join(name);
+            if self.exists(&p) {
+                return Ok(p);
+            }
+            if !folder.as_os_str().is_empty() {
+                searched.push(folder.to_path_buf());
+            }
+        }
+        if let Some(p) = include_dirs
+            .iter()
+            .map(|dir| dir.join(name))
+            .find(|p| self.exists(p))
+        {
+            return Ok(p);
+        }
+        searched.extend(include_dirs.iter().cloned());
+        if searched.is_empty() {
+            return Err(format!("No include directories given for {}", name));
+        }
+        let searched = searched
+            .iter()
+            .map(|dir| format!("\"{}\"", dir.display()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        Err(format!("Couldn't find {} in {}", name, searched))
+    }
+
+    /// Every file under `dir`, which is how a `ShaderLibrary` finds its shaders.
+    /// Sources that can't list their files return nothing.
+    fn files(&self, _dir: &Path) -> Vec<PathBuf> {
+        Vec::new()
+    }
+
+    /// Calls `on_change` whenever a file changes.
+    /// Returns false if this source can't report changes,
+    /// in which case a `Watch` watches the file system instead.
+    fn subscribe(&self, _on_change: ChangeCallback) -> bool {
+        false
+    }
+}
+
+/// Reads shaders from the file system.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FileSource;
+
+impl ShaderSource for FileSource {
+    fn read_bytes(&self, path: &Path) -> io::Result<Vec<u8>> {
+        std::fs::read(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn canonicalize(&self, path: &Path) -> PathBuf {
+        path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+    }
+
+    /// Symlinked directories aren't followed so a link can't make a loop.
+    fn files(&self, dir: &Path) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        find_files(dir, &mut files);
+        files
+    }
+}
+
+fn find_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|e| e.ok()) {
+        let file_type = match entry.file_type() {
+            Ok(file_type) => file_type,
+            Err(_) => continue,
+        };
+        let path = entry.path();
+        if file_type.is_dir() {
+            find_files(&path, files);
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+}
+
+/// Shaders kept in memory, for example unpacked from an archive or
+/// written by a test. Paths are compared after removing `.` and
+/// resolving `..` against the path itself, so `shaders/../common/x.glsl`
+/// is the file inserted as `common/x.glsl`.
+/// Changing a file notifies any `Watch` using this source.
+#[derive(Default)]
+pub struct MemorySource {
+    files: RwLock<HashMap<PathBuf, Vec<u8>>>,
+    subscribers: Mutex<Vec<ChangeCallback>>,
+}
+
+impl MemorySource {
+    pub fn new() -> Self {
+        MemorySource::default()
+    }
+
+    /// Adds or replaces the file at `path`.
+    pub fn insert<T, C>(&self, path: T, contents: C)
+    where
+        T: AsRef<Path>,
+        C: Into<Vec<u8>>,
+    {
+        let path = clean(path.as_ref());
+        if let Ok(mut files) = self.files.write() {
+            files.insert(path.clone(), contents.into());
+        }
+        self.notify(&path);
+    }
+
+    pub fn remove<T>(&self, path: T)
+    where
+        T: AsRef<Path>,
+    {
+        let path = clean(path.as_ref());
+        let removed = self
+            .files
+            .write()
+            .map(|mut files| files.remove(&path).is_some())
+            .unwrap_or(false);
+        if removed {
+            self.notify(&path);
+        }
+    }
+
+    fn notify(&self, path: &Path) {
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.retain(|on_change| on_change(path));
+        }
+    }
+}
+
+impl ShaderSource for MemorySource {
+    fn read_bytes(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.files
+            .read()
+            .ok()
+            .and_then(|files| files.get(&clean(path)).cloned())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such file in memory"))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.files
+            .read()
+            .map(|files| files.contains_key(&clean(path)))
+            .unwrap_or(false)
+    }
+
+    fn canonicalize(&self, path: &Path) -> PathBuf {
+        clean(path)
+    }
+
+    fn files(&self, dir: &Path) -> Vec<PathBuf> {
+        let dir = clean(dir);
+        let mut files = self
+            .files
+            .read()
+            .map(|files| files.keys().filter(|p| p.starts_with(&dir)).cloned().collect())
+            .unwrap_or_else(|_| Vec::new());
+        files.sort();
+        files
+    }
+
+    fn subscribe(&self, on_change: ChangeCallback) -> bool {
+        if let Ok(mut subscribers) = self.subscribers.lock() {
+            subscribers.push(on_change);
+        }
+        true
+    }
+}
+
+/// Removes `.` and resolves `..` without looking at the file system.
+/// A `..` that would leave the start of a relative path is kept.
+fn clean(path: &Path) -> PathBuf {
+    let mut clean = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => match clean.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    clean.pop();
+                }
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => (),
+                _ => clean.push(".."),
+            },
+            c => clean.push(c.as_os_str()),
+        }
+    }
+    clean
+}
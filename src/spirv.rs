@@ -0,0 +1,59 @@
+use crate::error::{CompileError, Error, SpirvError};
+use crate::reflection;
+use crate::source::{FileSource, ShaderSource};
+use crate::CompiledShader;
+use std::path::Path;
+
+const MAGIC: u32 = 0x0723_0203;
+/// The magic number, version, generator, bound and schema words.
+const HEADER_WORDS: usize = 5;
+
+/// Whether `path` is a SPIR-V binary rather than shader source.
+pub fn is_spirv<T>(path: T) -> bool
+where
+    T: AsRef<Path>,
+{
+    path.as_ref().extension().is_some_and(|ext| ext == "spv")
+}
+
+/// Loads a SPIR-V module from a file.
+pub fn load_spirv<T>(path: T) -> Result<CompiledShader, Error>
+where
+    T: AsRef<Path>,
+{
+    load_spirv_from(&FileSource, path.as_ref())
+}
+
+/// Loads a SPIR-V module from a file in `source`.
+pub(crate) fn load_spirv_from(source: &dyn ShaderSource, path: &Path) -> Result<CompiledShader, Error> {
+    let bytes = source
+        .read_bytes(path)
+        .map_err(|e| Error::Compile(CompileError::Open(path.to_path_buf(), e)))?;
+    load_spirv_bytes(&bytes)
+}
+
+/// Loads a SPIR-V module that was compiled ahead of time.
+/// Modules of either endianness are accepted.
+/// The entry point is the first one in the module.
+pub fn load_spirv_bytes(bytes: &[u8]) -> Result<CompiledShader, Error> {
+    if bytes.len() % 4 != 0 || bytes.len() < HEADER_WORDS * 4 {
+        return Err(Error::Spirv(SpirvError::Length(bytes.len())));
+    }
+    let words = bytes
+        .chunks(4)
+        .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]));
+    let spriv = match words.clone().next() {
+        Some(MAGIC) => words.collect::<Vec<_>>(),
+        Some(magic) if magic == MAGIC.swap_bytes() => words.map(u32::swap_bytes).collect(),
+        Some(magic) => return Err(Error::Spirv(SpirvError::Magic(magic))),
+        None => return Err(Error::Spirv(SpirvError::Length(bytes.len()))),
+    };
+    let entry_point = reflection::entry_point_name(&spriv)
+        .ok_or_else(|| Error::LoadingData("SPIR-V module has no entry point".to_string()))?;
+    Ok(CompiledShader {
+        spriv,
+        includes: Vec::new(),
+        warnings: Vec::new(),
+        entry_point,
+    })
+}
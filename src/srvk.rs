@@ -192,3 +192,20 @@ impl TryFrom<sr::types::ReflectFormat> for SpirvTy<Format> {
         Ok(SpirvTy { inner: t })
     }
 }
+
+/// Every format the conversion from `ReflectFormat` produces.
+/// The compile cache stores a format as its index in this list.
+pub const FORMATS: [Format; 12] = [
+    Format::R32Uint,
+    Format::R32Sint,
+    Format::R32Sfloat,
+    Format::R32G32Uint,
+    Format::R32G32Sint,
+    Format::R32G32Sfloat,
+    Format::R32G32B32Uint,
+    Format::R32G32B32Sint,
+    Format::R32G32B32Sfloat,
+    Format::R32G32B32A32Uint,
+    Format::R32G32B32A32Sint,
+    Format::R32G32B32A32Sfloat,
+];
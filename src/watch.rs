@@ -1,42 +1,148 @@
-use crate::error::Error;
+use crate::compiler::ShaderCompiler;
+use crate::config::{CompileConfig, Target};
+use crate::diagnostic::Diagnostic;
+use crate::error::{CompileError, Error};
 use crate::layouts::Entry;
-use crate::CompiledShaders;
-use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use crate::reflection;
+use crate::source::{FileSource, ShaderSource};
+use crate::spirv;
+use crate::{CompiledShader, CompiledShaders};
+use notify::{DebouncedEvent, PollWatcher, RecommendedWatcher, RecursiveMode, Watcher};
+use shaderc::ShaderKind;
+use std::io;
 use std::path::{Path, PathBuf};
-use std::sync::mpsc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
 use std::sync::mpsc::{Receiver, Sender};
 use std::thread;
+#[cfg(feature = "stream")]
+use std::pin::Pin;
+#[cfg(feature = "stream")]
+use std::sync::mpsc::TryRecvError;
+#[cfg(feature = "stream")]
+use std::task::{Context, Poll, Waker};
 use std::time::Duration;
 
+pub(crate) const DEFAULT_FREQUENCY: Duration = Duration::from_millis(500);
+
 pub struct Watch {
     _handler: Handler,
     pub rx: Receiver<Result<Message, Error>>,
+    state: Arc<Mutex<WatchState>>,
+    #[cfg(feature = "stream")]
+    waker: Arc<Mutex<Option<Waker>>>,
 }
 
-enum Loader {
-    Graphics(GraphicsLoader),
-    Compute(ComputeLoader),
+/// Called on the watcher thread with the result of every reload.
+pub type ReloadCallback = Box<dyn FnMut(&Result<Message, Error>) + Send>;
+
+/// The last shaders that loaded successfully and the outcome
+/// of the latest reload.
+#[derive(Clone, Default)]
+pub struct WatchState {
+    /// The last message that loaded without errors.
+    /// A failed reload never replaces it.
+    pub current: Option<Message>,
+    /// The error from the latest reload if it failed.
+    /// Cleared by the next successful reload.
+    pub error: Option<String>,
+    /// The compiler diagnostics of that error, empty if it
+    /// wasn't a compile error or the last reload succeeded.
+    pub diagnostics: Vec<Diagnostic>,
+    /// Incremented every time `current` is replaced.
+    pub generation: u64,
 }
 
-enum SrcPath {
-    Graphics(PathBuf, PathBuf),
-    Compute(PathBuf),
+/// How changes to the shader files are detected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchMode {
+    /// Use the native watcher and fall back to polling at the
+    /// watch frequency if it can't be set up.
+    Auto,
+    /// Only use the native watcher (inotify, FSEvents, ReadDirectoryChanges).
+    Native,
+    /// Check the modification times of the files at this interval.
+    /// Works on network and container filesystems that never
+    /// send native events.
+    Poll(Duration),
 }
 
-struct GraphicsLoader {
-    vertex: PathBuf,
-    fragment: PathBuf,
-    tx: Sender<Result<Message, Error>>,
+/// Builds a `Watch` over any combination of shader stages.
+pub struct WatchBuilder {
+    /// The path, stage and entry point of each stage.
+    stages: Vec<(PathBuf, ShaderKind, Option<String>)>,
+    frequency: Duration,
+    mode: WatchMode,
+    config: CompileConfig,
+    /// Applied to the config in `build` so it doesn't depend on the call order.
+    target: Option<Target>,
+    callback: Option<ReloadCallback>,
+    source: Arc<dyn ShaderSource>,
 }
 
-struct ComputeLoader {
-    compute: PathBuf,
+struct Loader {
+    stages: Vec<Stage>,
+    source: Arc<dyn ShaderSource>,
+    /// Whether changes come from the file watcher, whose paths
+    /// need to be normalized before they are compared.
+    watches_files: bool,
+    state: Arc<Mutex<WatchState>>,
+    callback: Option<ReloadCallback>,
+    /// Set when the `Watch` is dropped.
+    shutdown: Arc<AtomicBool>,
+    #[cfg(feature = "stream")]
+    waker: Arc<Mutex<Option<Waker>>>,
+    /// Stages recompiled since the last message was sent.
+    changed: Vec<ShaderKind>,
+    /// Files that were removed and have not come back yet.
+    missing: Vec<PathBuf>,
     tx: Sender<Result<Message, Error>>,
 }
 
+struct Stage {
+    path: PathBuf,
+    kind: ShaderKind,
+    /// Overrides the entry point of the config for this stage.
+    entry_point: Option<String>,
+    /// Files included by the last successful compile of this stage.
+    includes: Vec<PathBuf>,
+    loaded: Option<(CompiledShader, Entry)>,
+}
+
+pub(crate) enum Backend {
+    Native(RecommendedWatcher),
+    Poll(PollWatcher),
+}
+
+/// Keeps the watched directories in sync with the files the
+/// loader depends on.
+struct Watched {
+    watcher: Backend,
+    dirs: Vec<PathBuf>,
+}
+
+#[derive(Clone)]
 pub struct Message {
     pub shaders: CompiledShaders,
+    /// The reflected entry of each stage in pipeline order.
+    pub entries: Vec<(ShaderKind, Entry)>,
+    /// The combined entry of all stages.
+    /// The input comes from the first stage, the output from the
+    /// last stage and the layout is the union of all stages.
     pub entry: Entry,
+    /// The stages that were recompiled for this message.
+    /// The first message contains every stage.
+    pub changed: Vec<ShaderKind>,
+}
+
+impl Message {
+    /// The reflected entry for `kind` if the pipeline has that stage.
+    pub fn stage_entry(&self, kind: ShaderKind) -> Option<&Entry> {
+        self.entries
+            .iter()
+            .find(|(k, _)| *k == kind)
+            .map(|(_, entry)| entry)
+    }
 }
 
 impl Watch {
@@ -46,163 +152,688 @@ impl Watch {
     where
         T: AsRef<Path>,
     {
-        let src_path = SrcPath::Graphics(
-            vertex.as_ref().to_path_buf(),
-            fragment.as_ref().to_path_buf()
-            );
-        let (handler, rx) = create_watch(
-            src_path,
-            frequency,
-        )?;
-        Ok(Watch {
-            _handler: handler,
-            rx,
-        })
+        WatchBuilder::new()
+            .stage(vertex, ShaderKind::Vertex)
+            .stage(fragment, ShaderKind::Fragment)
+            .frequency(frequency)
+            .build()
     }
 
     pub fn create_compute<T>(compute: T, frequency: Duration) -> Result<Self, Error>
     where
         T: AsRef<Path>,
     {
-        let src_path = SrcPath::Compute(
-            compute.as_ref(). to_path_buf());
-        let (handler, rx) = create_watch(
-            src_path,
-            frequency,
-        )?;
+        WatchBuilder::new()
+            .stage(compute, ShaderKind::Compute)
+            .frequency(frequency)
+            .build()
+    }
+
+    /// A copy of the current state.
+    pub fn state(&self) -> WatchState {
+        self.state.lock().map(|s| s.clone()).unwrap_or_default()
+    }
+
+    /// The generation of the current good message.
+    /// Cheaper than `state` for checking if anything changed.
+    pub fn generation(&self) -> u64 {
+        self.state.lock().map(|s| s.generation).unwrap_or(0)
+    }
+
+    /// The last message that loaded without errors.
+    pub fn current(&self) -> Option<Message> {
+        self.state.lock().ok().and_then(|s| s.current.clone())
+    }
+
+    /// The error from the latest reload if it failed.
+    pub fn error(&self) -> Option<String> {
+        self.state.lock().ok().and_then(|s| s.error.clone())
+    }
+
+    /// The compiler diagnostics of the latest reload if it failed to compile.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.state.lock().map(|s| s.diagnostics.clone()).unwrap_or_default()
+    }
+}
+
+impl WatchBuilder {
+    pub fn new() -> Self {
+        WatchBuilder {
+            stages: Vec::new(),
+            frequency: DEFAULT_FREQUENCY,
+            mode: WatchMode::Auto,
+            config: CompileConfig::default(),
+            target: None,
+            callback: None,
+            source: Arc::new(FileSource),
+        }
+    }
+
+    /// Adds the shader at `path` as the `kind` stage of the pipeline.
+    /// Files ending in `.spv` are loaded as SPIR-V instead of compiled.
+    pub fn stage<T>(mut self, path: T, kind: ShaderKind) -> Self
+    where
+        T: AsRef<Path>,
+    {
+        self.stages.push((path.as_ref().to_path_buf(), kind, None));
+        self
+    }
+
+    /// Adds a stage whose entry point is `entry_point` instead of the
+    /// one in the config, for example `VSMain` and `PSMain` in one HLSL file.
+    pub fn stage_with_entry<T>(mut self, path: T, kind: ShaderKind, entry_point: &str) -> Self
+    where
+        T: AsRef<Path>,
+    {
+        self.stages
+            .push((path.as_ref().to_path_buf(), kind, Some(entry_point.to_string())));
+        self
+    }
+
+    /// Adds the shader at `path` as the stage `load_auto` finds.
+    /// If that needs the source `build` compiles it once to find out.
+    /// SPIR-V binaries are loaded and their stage is read from the module.
+    pub fn auto_stage<T>(self, path: T) -> Self
+    where
+        T: AsRef<Path>,
+    {
+        self.stage(path, ShaderKind::InferFromSource)
+    }
+
+    /// How often the watcher will check the directories.
+    pub fn frequency(mut self, frequency: Duration) -> Self {
+        self.frequency = frequency;
+        self
+    }
+
+    /// How changes are detected. Defaults to `WatchMode::Auto`.
+    pub fn mode(mut self, mode: WatchMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// The settings every stage is compiled with on each reload.
+    pub fn config(mut self, config: CompileConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// The Vulkan version every stage is compiled for.
+    /// Overrides the target of the config.
+    pub fn target(mut self, target: Target) -> Self {
+        self.target = Some(target);
+        self
+    }
+
+    /// Where the stages and their includes are read from.
+    /// Defaults to the file system. If the source can report changes
+    /// it is used instead of watching the file system.
+    pub fn source(mut self, source: Arc<dyn ShaderSource>) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Runs `callback` on the watcher thread after every reload,
+    /// before the result is sent to `Watch::rx`.
+    pub fn on_reload<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(&Result<Message, Error>) + Send + 'static,
+    {
+        self.callback = Some(Box::new(callback));
+        self
+    }
+
+    /// Starts watching the stages.
+    /// The stages are compiled on the watcher thread and the
+    /// first `Message` is sent as soon as they are done.
+    pub fn build(mut self) -> Result<Watch, Error> {
+        if self.stages.is_empty() {
+            return Err(Error::InvalidPipeline("No stages to watch".to_string()));
+        }
+        if let Some(target) = self.target {
+            self.config = self.config.target(target);
+        }
+        for (path, kind, _) in &mut self.stages {
+            if *kind != ShaderKind::InferFromSource {
+                continue;
+            }
+            *kind = match crate::shader_kind_from_path(&path) {
+                Some(kind) => kind,
+                None if spirv::is_spirv(&path) => {
+                    let shader = spirv::load_spirv_from(&*self.source, path)?;
+                    reflection::detected_kind(ShaderKind::InferFromSource, &shader)?
+                }
+                None => ShaderCompiler::with_source(self.config.clone(), self.source.clone())?
+                    .load_auto(&path)?
+                    .0,
+            };
+        }
+        self.stages.sort_by_key(|(_, kind, _)| stage_order(*kind));
+        for (i, (_, kind, _)) in self.stages.iter().enumerate() {
+            if self.stages[..i].iter().any(|(_, k, _)| k == kind) {
+                return Err(Error::InvalidPipeline(format!("Duplicate {:?} stage", kind)));
+            }
+        }
+        let compute = self.stages.iter().any(|(_, kind, _)| *kind == ShaderKind::Compute);
+        if compute && self.stages.len() > 1 {
+            return Err(Error::InvalidPipeline(
+                "A compute stage can't be combined with other stages".to_string(),
+            ));
+        }
+        let (loader, rx) = Loader::new(self.stages, self.source, self.callback);
+        let state = loader.state.clone();
+        #[cfg(feature = "stream")]
+        let waker = loader.waker.clone();
+        let handler = create_watch(loader, self.config, self.mode, self.frequency)?;
         Ok(Watch {
             _handler: handler,
             rx,
+            state,
+            #[cfg(feature = "stream")]
+            waker,
         })
     }
 }
 
-impl GraphicsLoader {
-    fn create(vertex: PathBuf, fragment: PathBuf) -> (Self, Receiver<Result<Message, Error>>) {
+impl Default for WatchBuilder {
+    fn default() -> Self {
+        WatchBuilder::new()
+    }
+}
+
+impl Loader {
+    fn new(
+        stages: Vec<(PathBuf, ShaderKind, Option<String>)>,
+        source: Arc<dyn ShaderSource>,
+        callback: Option<ReloadCallback>,
+    ) -> (Self, Receiver<Result<Message, Error>>) {
         let (tx, rx) = mpsc::channel();
-        let loader = GraphicsLoader {
-            vertex,
-            fragment,
+        let state = Arc::new(Mutex::new(WatchState::default()));
+        let stages = stages
+            .into_iter()
+            .map(|(path, kind, entry_point)| Stage {
+                path,
+                kind,
+                entry_point,
+                includes: Vec::new(),
+                loaded: None,
+            })
+            .collect();
+        let loader = Loader {
+            stages,
+            source,
+            watches_files: false,
+            state,
+            callback,
+            shutdown: Arc::new(AtomicBool::new(false)),
+            #[cfg(feature = "stream")]
+            waker: Arc::new(Mutex::new(None)),
+            changed: Vec::new(),
+            missing: Vec::new(),
             tx,
         };
-        loader.reload();
         (loader, rx)
     }
 
-    fn reload(&self) {
-//        match crate::load(&self.vertex, &self.fragment) {
-//            Ok(shaders) => {
-//                let entry = crate::parse(&shaders);
-//                let msg = entry.map(|entry| Message { shaders, entry });
-//                self.tx.send(msg).ok()
-//            }
-//            Err(e) => self.tx.send(Err(e)).ok(),
-//        };
+    /// Records the result in the state and sends it.
+    fn send(&mut self, msg: Result<Message, Error>) {
+        if self.shutdown.load(Ordering::SeqCst) {
+            return;
+        }
+        if let Ok(mut state) = self.state.lock() {
+            match &msg {
+                Ok(msg) => {
+                    state.current = Some(msg.clone());
+                    state.error = None;
+                    state.diagnostics.clear();
+                    state.generation += 1;
+                }
+                Err(e) => {
+                    state.error = Some(e.to_string());
+                    state.diagnostics = e.diagnostics().to_vec();
+                }
+            }
+        }
+        if let Some(callback) = &mut self.callback {
+            callback(&msg);
+        }
+        self.tx.send(msg).ok();
+        #[cfg(feature = "stream")]
+        {
+            if let Some(waker) = self.waker.lock().ok().and_then(|mut w| w.take()) {
+                waker.wake();
+            }
+        }
     }
-}
 
-impl ComputeLoader {
-    fn create(compute: PathBuf) -> (Self, Receiver<Result<Message, Error>>) {
-        let (tx, rx) = mpsc::channel();
-        let loader = ComputeLoader {
-            compute,
-            tx,
+    /// All the files the stages depend on.
+    fn files(&self) -> impl Iterator<Item = &Path> {
+        self.stages.iter().flat_map(|stage| {
+            std::iter::once(stage.path.as_path()).chain(stage.includes.iter().map(|p| p.as_path()))
+        })
+    }
+
+    /// Makes `path` comparable with the paths of the stages.
+    fn normalize(&self, path: &Path) -> PathBuf {
+        if self.watches_files {
+            normalize(path)
+        } else {
+            self.source.canonicalize(path)
+        }
+    }
+
+    fn reload_all(&mut self, compiler: &mut ShaderCompiler) {
+        let all = (0..self.stages.len()).collect::<Vec<_>>();
+        self.reload(compiler, &all);
+    }
+
+    /// Recompiles the stages that depend on `file`.
+    /// Returns false if no stage depends on it.
+    fn reload_file(&mut self, compiler: &mut ShaderCompiler, file: &Path) -> bool {
+        let file = self.normalize(file);
+        let stages = self
+            .stages
+            .iter()
+            .enumerate()
+            .filter(|(_, stage)| stage.depends_on(&file))
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        if stages.is_empty() {
+            return false;
+        }
+        self.missing.retain(|p| *p != file);
+        self.reload(compiler, &stages);
+        true
+    }
+
+    /// Reports an error if a file the stages depend on is gone.
+    /// The stages are reloaded once the file is created again.
+    fn remove_file(&mut self, file: &Path) {
+        let file = self.normalize(file);
+        if self.source.exists(&file) || !self.stages.iter().any(|stage| stage.depends_on(&file)) {
+            return;
+        }
+        let e = io::Error::new(io::ErrorKind::NotFound, "the file was removed");
+        if !self.missing.contains(&file) {
+            self.missing.push(file.clone());
+        }
+        self.send(Err(Error::Compile(CompileError::Open(file, e))));
+    }
+
+    fn is_missing(&self, file: &Path) -> bool {
+        self.missing.contains(&self.normalize(file))
+    }
+
+    /// Handles an event from the watcher.
+    /// Returns true if any stage was reloaded.
+    fn handle(&mut self, compiler: &mut ShaderCompiler, event: DebouncedEvent) -> bool {
+        match event {
+            DebouncedEvent::Create(path) | DebouncedEvent::Write(path) => {
+                self.reload_file(compiler, &path)
+            }
+            // A permission change alone doesn't change the source but it can be
+            // the last event of a file that was recreated.
+            DebouncedEvent::Chmod(path) => {
+                self.is_missing(&path) && self.reload_file(compiler, &path)
+            }
+            DebouncedEvent::Remove(path) => {
+                self.remove_file(&path);
+                false
+            }
+            // Editors that save atomically rename a temporary file over the original.
+            DebouncedEvent::Rename(from, to) => {
+                self.remove_file(&from);
+                self.reload_file(compiler, &to)
+            }
+            DebouncedEvent::Rescan => {
+                self.reload_all(compiler);
+                true
+            }
+            DebouncedEvent::Error(e, _) => {
+                self.send(Err(Error::FileWatch(e)));
+                false
+            }
+            // Wait for the debounced event so a half written file isn't loaded.
+            DebouncedEvent::NoticeWrite(_) | DebouncedEvent::NoticeRemove(_) => false,
+        }
+    }
+
+    fn reload(&mut self, compiler: &mut ShaderCompiler, stages: &[usize]) {
+        let mut error = None;
+        for &i in stages {
+            let stage = &mut self.stages[i];
+            match load_stage(compiler, &stage.path, stage.kind, stage.entry_point.as_deref()) {
+                Ok(loaded) => {
+                    stage.includes = loaded.0.includes.clone();
+                    stage.loaded = Some(loaded);
+                    if !self.changed.contains(&stage.kind) {
+                        self.changed.push(stage.kind);
+                    }
+                }
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+        if let Some(e) = error {
+            self.send(Err(e));
+            return;
+        }
+        let stages = self
+            .stages
+            .iter()
+            .filter_map(|stage| stage.loaded.as_ref().map(|loaded| (stage.kind, loaded)))
+            .collect::<Vec<_>>();
+        if stages.len() != self.stages.len() {
+            return;
+        }
+        let entries = stages
+            .iter()
+            .map(|(kind, (_, entry))| (*kind, entry.clone()))
+            .collect::<Vec<_>>();
+        let entry = reflection::merge_entries(entries.iter().map(|(_, entry)| entry));
+        let shaders = CompiledShaders {
+            stages: stages
+                .iter()
+                .map(|(kind, (shader, _))| (*kind, shader.clone()))
+                .collect(),
         };
-        loader.reload();
-        (loader, rx)
+        let changed = std::mem::take(&mut self.changed);
+        let msg = Message {
+            shaders,
+            entries,
+            entry,
+            changed,
+        };
+        self.send(Ok(msg));
     }
+}
 
-    fn reload(&self) {
-//        match crate::load_compute(&self.compute) {
-//            Ok(shaders) => {
-//                let entry = crate::parse_compute(&shaders);
-//                let msg = entry.map(|entry| Message { shaders, entry });
-//                self.tx.send(msg).ok()
-//            }
-//            Err(e) => self.tx.send(Err(e)).ok(),
-//        };
+impl Stage {
+    fn depends_on(&self, file: &Path) -> bool {
+        self.path == file || self.includes.iter().any(|p| p == file)
     }
 }
 
-impl Loader {
-    fn reload(&self) {
+impl Backend {
+    /// Creates the backend for `mode` and sets it up with `watch`.
+    /// In `WatchMode::Auto` a native watcher that can't be created
+    /// or set up is replaced by polling at `frequency`.
+    pub(crate) fn create<T, F>(
+        mode: WatchMode,
+        tx: Sender<DebouncedEvent>,
+        frequency: Duration,
+        watch: F,
+    ) -> Result<T, Error>
+    where
+        F: Fn(Backend) -> Result<T, Error>,
+    {
+        match mode {
+            WatchMode::Native => Backend::native(tx, frequency).and_then(&watch),
+            WatchMode::Poll(interval) => Backend::poll(tx, interval).and_then(&watch),
+            WatchMode::Auto => Backend::native(tx.clone(), frequency)
+                .and_then(&watch)
+                .or_else(|_| Backend::poll(tx, frequency).and_then(&watch)),
+        }
+    }
+
+    fn native(tx: Sender<DebouncedEvent>, frequency: Duration) -> Result<Self, Error> {
+        Watcher::new(tx, frequency)
+            .map(Backend::Native)
+            .map_err(Error::FileWatch)
+    }
+
+    fn poll(tx: Sender<DebouncedEvent>, interval: Duration) -> Result<Self, Error> {
+        Watcher::new(tx, interval)
+            .map(Backend::Poll)
+            .map_err(Error::FileWatch)
+    }
+
+    pub(crate) fn watch(&mut self, dir: &Path, mode: RecursiveMode) -> notify::Result<()> {
+        match self {
+            Backend::Native(w) => w.watch(dir, mode),
+            Backend::Poll(w) => w.watch(dir, mode),
+        }
+    }
+
+    fn unwatch(&mut self, dir: &Path) -> notify::Result<()> {
         match self {
-            Loader::Graphics(g) => g.reload(),
-            Loader::Compute(g) => g.reload(),
+            Backend::Native(w) => w.unwatch(dir),
+            Backend::Poll(w) => w.unwatch(dir),
         }
     }
 }
 
-struct Handler {
-    thread_tx: mpsc::Sender<()>,
-    handle: Option<thread::JoinHandle<()>>,
-    _watcher: RecommendedWatcher,
+impl Watched {
+    /// Creates the backend for `mode` and watches the stages of `loader`.
+    fn create(
+        mode: WatchMode,
+        tx: Sender<DebouncedEvent>,
+        frequency: Duration,
+        loader: &Loader,
+    ) -> Result<Self, Error> {
+        Backend::create(mode, tx, frequency, |watcher| {
+            let mut watched = Watched {
+                watcher,
+                dirs: Vec::new(),
+            };
+            watched.update(loader.files()).map(|_| watched)
+        })
+    }
+
+    /// Watches the directories of `files` and stops watching
+    /// directories that are no longer needed.
+    fn update<'a, I>(&mut self, files: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = &'a Path>,
+    {
+        let mut needed: Vec<PathBuf> = Vec::new();
+        for file in files {
+            let dir = parent_dir(file);
+            if !needed.contains(&dir) {
+                needed.push(dir);
+            }
+        }
+        for dir in &self.dirs {
+            if !needed.contains(dir) {
+                self.watcher.unwatch(dir).ok();
+            }
+        }
+        self.dirs.retain(|dir| needed.contains(dir));
+        let mut result = Ok(());
+        for dir in needed {
+            if self.dirs.contains(&dir) {
+                continue;
+            }
+            match self.watcher.watch(&dir, RecursiveMode::NonRecursive) {
+                Ok(()) => self.dirs.push(dir),
+                Err(e) => result = Err(Error::FileWatch(e)),
+            }
+        }
+        result
+    }
+}
+
+/// Loads the shader at `path` as a `kind` stage.
+/// `entry_point` overrides the entry point of the compiler's config.
+pub(crate) fn load_stage(
+    compiler: &mut ShaderCompiler,
+    path: &Path,
+    kind: ShaderKind,
+    entry_point: Option<&str>,
+) -> Result<(CompiledShader, Entry), Error> {
+    if spirv::is_spirv(path) {
+        let mut shader = compiler.load_spirv(path)?;
+        if let Some(entry_point) = entry_point {
+            shader.entry_point = entry_point.to_string();
+        }
+        let entry = reflection::reflect(kind, &shader)?;
+        return Ok((shader, entry));
+    }
+    match entry_point {
+        Some(entry_point) => {
+            let config = compiler.config().clone();
+            compiler.set_config(config.clone().entry_point(entry_point));
+            let loaded = compiler.load_reflected(path, kind);
+            compiler.set_config(config);
+            loaded
+        }
+        None => compiler.load_reflected(path, kind),
+    }
+}
+
+/// Position of a stage in the pipeline.
+fn stage_order(kind: ShaderKind) -> usize {
+    match kind {
+        ShaderKind::Task => 0,
+        ShaderKind::Mesh => 1,
+        ShaderKind::Vertex => 2,
+        ShaderKind::TessControl => 3,
+        ShaderKind::TessEvaluation => 4,
+        ShaderKind::Geometry => 5,
+        ShaderKind::Fragment => 6,
+        _ => 7,
+    }
+}
+
+/// Makes paths comparable with the paths reported by the watcher.
+/// Only the directory is canonicalized so files that were removed
+/// can still be matched.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let dir = parent_dir(path);
+    match (dir.canonicalize(), path.file_name()) {
+        (Ok(dir), Some(name)) => dir.join(name),
+        _ => path.to_path_buf(),
+    }
+}
+
+fn parent_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
+/// Stops the watcher thread when the `Watch` is dropped.
+pub(crate) struct Handler {
+    pub(crate) shutdown: Arc<AtomicBool>,
+    /// Wakes the thread if it is blocked waiting for events.
+    pub(crate) notify_tx: Sender<DebouncedEvent>,
 }
 
 impl Drop for Handler {
     fn drop(&mut self) {
-        self.thread_tx.send(()).ok();
-        if let Some(h) = self.handle.take() {
-            h.join().ok();
-        }
+        // The thread isn't joined so dropping never waits on a compile.
+        // It finishes any compile in progress without sending the
+        // result and then exits on its own.
+        self.shutdown.store(true, Ordering::SeqCst);
+        self.notify_tx.send(DebouncedEvent::Rescan).ok();
     }
 }
 
 fn create_watch(
-    src_path: SrcPath,
-    frequency: Duration
-) -> Result<(Handler, mpsc::Receiver<Result<Message, Error>>), Error> {
+    mut loader: Loader,
+    config: CompileConfig,
+    mode: WatchMode,
+    frequency: Duration,
+) -> Result<Handler, Error> {
     let (notify_tx, notify_rx) = mpsc::channel();
-    let (thread_tx, thread_rx) = mpsc::channel();
-    let mut watcher: RecommendedWatcher =
-        Watcher::new(notify_tx, frequency).map_err(Error::FileWatch)?;
-
-    let (loader, rx) = match src_path {
-        SrcPath::Graphics(vert_path, frag_path) => {
-            let mut vp = vert_path.clone();
-            let mut fp = frag_path.clone();
-            vp.pop();
-            fp.pop();
-            watcher
-                .watch(&vp, RecursiveMode::NonRecursive)
-                .map_err(Error::FileWatch)?;
-            if vp != fp {
-                watcher
-                    .watch(&fp, RecursiveMode::NonRecursive)
-                    .map_err(Error::FileWatch)?;
-            }
-
-            let (loader, rx) = GraphicsLoader::create(vert_path, frag_path);
-            (Loader::Graphics(loader), rx)
-        }
-        SrcPath::Compute(compute_path) => {
-            let mut cp = compute_path.clone();
-            cp.pop();
-            watcher
-                .watch(&cp, RecursiveMode::NonRecursive)
-                .map_err(Error::FileWatch)?;
-
-            let (loader, rx) = ComputeLoader::create(compute_path);
-            (Loader::Compute(loader), rx)
+    let tx = notify_tx.clone();
+    let subscribed = loader.source.subscribe(Box::new(move |path| {
+        tx.send(DebouncedEvent::Write(path.to_path_buf())).is_ok()
+    }));
+    // Watch the stages now so errors are returned from `build`.
+    // Includes are added once the stages are compiled.
+    let mut watched = if subscribed {
+        for stage in &mut loader.stages {
+            stage.path = loader.source.canonicalize(&stage.path);
+        }
+        None
+    } else {
+        loader.watches_files = true;
+        for stage in &mut loader.stages {
+            stage.path = normalize(&stage.path);
         }
+        Some(Watched::create(mode, notify_tx.clone(), frequency, &loader)?)
     };
 
+    let shutdown = loader.shutdown.clone();
+    thread::spawn(move || {
+        // The compiler can't be sent between threads so
+        // the session is created on the watcher thread.
+        let mut compiler = match ShaderCompiler::with_source(config, loader.source.clone()) {
+            Ok(compiler) => compiler,
+            Err(e) => {
+                loader.send(Err(e));
+                return;
+            }
+        };
+        let update = |loader: &mut Loader, watched: &mut Option<Watched>| {
+            if let Some(watched) = watched {
+                if let Err(e) = watched.update(loader.files()) {
+                    loader.send(Err(e));
+                }
+            }
+        };
+        loader.reload_all(&mut compiler);
+        update(&mut loader, &mut watched);
+        for event in notify_rx.iter() {
+            if loader.shutdown.load(Ordering::SeqCst) {
+                break;
+            }
+            if loader.handle(&mut compiler, event) {
+                update(&mut loader, &mut watched);
+            }
+        }
+    });
+    Ok(Handler {
+        shutdown,
+        notify_tx,
+    })
+}
+
+#[cfg(feature = "stream")]
+impl Watch {
+    /// Turns the watch into a `Stream` of reload results.
+    pub fn into_stream(self) -> WatchStream {
+        WatchStream { watch: self }
+    }
+}
+
+/// A `Stream` of the results sent by a `Watch`.
+#[cfg(feature = "stream")]
+pub struct WatchStream {
+    watch: Watch,
+}
 
-    let handle = thread::spawn(move || 'watch_loop: loop {
-        if thread_rx.try_recv().is_ok() {
-            break 'watch_loop;
+#[cfg(feature = "stream")]
+impl WatchStream {
+    /// The watch behind the stream, for access to its state.
+    pub fn watch(&self) -> &Watch {
+        &self.watch
+    }
+
+    fn try_next(&self) -> Poll<Option<Result<Message, Error>>> {
+        match self.watch.rx.try_recv() {
+            Ok(msg) => Poll::Ready(Some(msg)),
+            Err(TryRecvError::Disconnected) => Poll::Ready(None),
+            Err(TryRecvError::Empty) => Poll::Pending,
         }
-        if let Ok(notify::DebouncedEvent::Create(_)) | Ok(notify::DebouncedEvent::Write(_)) =
-            notify_rx.recv_timeout(Duration::from_secs(1))
-        {
-            loader.reload();
+    }
+}
+
+#[cfg(feature = "stream")]
+impl futures::Stream for WatchStream {
+    type Item = Result<Message, Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        if let Poll::Ready(next) = self.try_next() {
+            return Poll::Ready(next);
         }
-    });
-    let handle = Some(handle);
-    let handler = Handler {
-        thread_tx,
-        handle,
-        _watcher: watcher,
-    };
-    Ok((handler, rx))
+        if let Ok(mut waker) = self.watch.waker.lock() {
+            *waker = Some(cx.waker().clone());
+        }
+        // A message may have arrived before the waker was stored.
+        self.try_next()
+    }
 }
@@ -9,6 +9,7 @@ use vulkano::descriptor::pipeline_layout::{PipelineLayoutDesc, PipelineLayoutDes
 use vulkano::format::*;
 use vulkano::pipeline::shader::ShaderInterfaceDefEntry;
 use shaderc::ShaderKind;
+use std::time::Duration;
 
 fn setup() {
     color_backtrace::install();
@@ -371,3 +372,1118 @@ fn test_shade4() {
         &descriptor_layout(&vert_target.layout),
     );
 }
+
+fn shader_path<T>(input: T) -> PathBuf
+    where
+        T: AsRef<Path>,
+{
+    let project_root = std::env::current_dir().expect("failed to get root directory");
+    let mut path = project_root.clone();
+    path.push(PathBuf::from("tests/shaders/"));
+    path.push(input);
+    path
+}
+
+/// Waits for the next message from `watch` and panics if it failed to load.
+fn next_message(watch: &Watch) -> Message {
+    watch
+        .rx
+        .recv_timeout(Duration::from_secs(5))
+        .expect("No message from watch")
+        .expect("Failed to load shaders")
+}
+
+/// Waits for the next message from `watch` and panics if it loaded.
+fn expect_error(watch: &Watch) -> Error {
+    match watch.rx.recv_timeout(Duration::from_secs(5)).expect("No message from watch") {
+        Ok(_) => panic!("Expected the reload to fail"),
+        Err(e) => e,
+    }
+}
+
+#[test]
+fn test_watch() {
+    setup();
+    let watch = Watch::create(
+        shader_path("vert2.glsl"),
+        shader_path("frag2.glsl"),
+        Duration::from_millis(50),
+    )
+    .expect("Failed to create watch");
+    let message = next_message(&watch);
+
+    let vert_entry = parse("vert2.glsl", ShaderKind::Vertex);
+    let frag_entry = parse("frag2.glsl", ShaderKind::Fragment);
+    do_test(&message.entry.input, &vert_entry.input);
+    do_test(&message.entry.output, &frag_entry.output);
+    assert!(message.shaders.vertex().is_some());
+    assert!(message.shaders.fragment().is_some());
+}
+
+#[test]
+fn test_watch_compute() {
+    setup();
+    let dir = temp_dir("watch_compute");
+    let compute = dir.join("compute.glsl");
+    std::fs::copy(shader_path("compute.glsl"), &compute).unwrap();
+
+    let watch = Watch::create_compute(&compute, Duration::from_millis(50))
+        .expect("Failed to create watch");
+    let message = next_message(&watch);
+    assert!(message.shaders.compute().is_some());
+    assert!(message.entry.input.is_none());
+    assert_eq!(message.entry.layout.layout_data.num_bindings.get(&0), Some(&1));
+
+    std::fs::write(&compute, "#version 450
+
+layout(local_size_x = 64, local_size_y = 1, local_size_z = 1) in;
+
+layout(set = 0, binding = 0) buffer Data {
+  uint data[];
+} buf;
+
+layout(set = 0, binding = 1) buffer Scale {
+  uint scale;
+} scale;
+
+void main() {
+  uint idx = gl_GlobalInvocationID.x;
+  buf.data[idx] *= scale.scale;
+}
+").unwrap();
+    let message = next_message(&watch);
+    assert_eq!(message.changed, vec![ShaderKind::Compute]);
+    assert!(message.entry.input.is_none());
+    assert!(message.entry.output.is_none());
+    assert_eq!(message.entry.layout.layout_data.num_bindings.get(&0), Some(&2));
+}
+
+#[test]
+fn test_watch_builder() {
+    setup();
+    let watch = WatchBuilder::new()
+        .stage(shader_path("frag2.glsl"), ShaderKind::Fragment)
+        .stage(shader_path("vert2.glsl"), ShaderKind::Vertex)
+        .frequency(Duration::from_millis(50))
+        .build()
+        .expect("Failed to create watch");
+    let message = next_message(&watch);
+
+    let kinds = message.entries.iter().map(|(kind, _)| *kind).collect::<Vec<_>>();
+    assert_eq!(kinds, vec![ShaderKind::Vertex, ShaderKind::Fragment]);
+    let frag_entry = parse("frag2.glsl", ShaderKind::Fragment);
+    do_test(&message.stage_entry(ShaderKind::Fragment).map(|e| &e.input), &Some(&frag_entry.input));
+
+    let duplicate = WatchBuilder::new()
+        .stage(shader_path("vert1.glsl"), ShaderKind::Vertex)
+        .stage(shader_path("vert2.glsl"), ShaderKind::Vertex)
+        .build();
+    assert!(duplicate.is_err());
+
+    let mixed = WatchBuilder::new()
+        .stage(shader_path("vert1.glsl"), ShaderKind::Vertex)
+        .stage(shader_path("compute.glsl"), ShaderKind::Compute)
+        .build();
+    match mixed {
+        Err(Error::InvalidPipeline(_)) => (),
+        _ => panic!("Combined a compute stage with a vertex stage"),
+    }
+    // The compute stage on its own is a valid pipeline.
+    WatchBuilder::new()
+        .stage(shader_path("compute.glsl"), ShaderKind::Compute)
+        .build()
+        .expect("Failed to watch the compute stage");
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let mut dir = std::env::temp_dir();
+    dir.push("shade_runner_tests");
+    dir.push(name);
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir).expect("failed to clear temp directory");
+    }
+    std::fs::create_dir_all(&dir).expect("failed to create temp directory");
+    dir
+}
+
+const INCLUDING_FRAG: &str = "#version 450
+#extension GL_GOOGLE_include_directive : require
+#include \"../common/common.glsl\"
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+  f_color = color();
+}
+";
+
+#[test]
+fn test_watch_includes() {
+    setup();
+    let dir = temp_dir("watch_includes");
+    std::fs::create_dir_all(dir.join("frag")).unwrap();
+    std::fs::create_dir_all(dir.join("common")).unwrap();
+    let frag = dir.join("frag/frag.glsl");
+    let common = dir.join("common/common.glsl");
+    std::fs::write(&frag, INCLUDING_FRAG).unwrap();
+    std::fs::write(&common, "vec4 color() { return vec4(1.0); }\n").unwrap();
+
+    let watch = WatchBuilder::new()
+        .stage(&frag, ShaderKind::Fragment)
+        .frequency(Duration::from_millis(50))
+        .build()
+        .expect("Failed to create watch");
+    let message = next_message(&watch);
+    let includes = &message.shaders.fragment().unwrap().includes;
+    assert_eq!(includes, &vec![common.canonicalize().unwrap()]);
+
+    std::fs::write(&common, "vec4 color() { return vec4(0.5); }\n").unwrap();
+    next_message(&watch);
+}
+
+#[test]
+fn test_watch_changed_stages() {
+    setup();
+    let dir = temp_dir("watch_changed");
+    let vert = dir.join("vert.glsl");
+    let frag = dir.join("frag.glsl");
+    std::fs::copy(shader_path("vert1.glsl"), &vert).unwrap();
+    std::fs::copy(shader_path("frag1.glsl"), &frag).unwrap();
+
+    let watch = Watch::create(&vert, &frag, Duration::from_millis(50)).expect("Failed to create watch");
+    let message = next_message(&watch);
+    assert_eq!(message.changed, vec![ShaderKind::Vertex, ShaderKind::Fragment]);
+
+    std::fs::write(dir.join("notes.txt"), "not a shader").unwrap();
+    assert!(watch.rx.recv_timeout(Duration::from_millis(500)).is_err());
+
+    std::fs::copy(shader_path("frag2.glsl"), &frag).unwrap();
+    std::fs::copy(shader_path("vert2.glsl"), &vert).unwrap();
+    let mut changed = Vec::new();
+    while changed.len() < 2 {
+        let message = next_message(&watch);
+        changed.extend(message.changed);
+    }
+    assert!(changed.contains(&ShaderKind::Vertex));
+    assert!(changed.contains(&ShaderKind::Fragment));
+}
+
+#[test]
+fn test_watch_atomic_save() {
+    setup();
+    let dir = temp_dir("watch_atomic");
+    let frag = dir.join("frag.glsl");
+    std::fs::copy(shader_path("frag1.glsl"), &frag).unwrap();
+
+    let watch = WatchBuilder::new()
+        .stage(&frag, ShaderKind::Fragment)
+        .frequency(Duration::from_millis(50))
+        .build()
+        .expect("Failed to create watch");
+    next_message(&watch);
+
+    // Save by renaming a temporary file over the shader.
+    let tmp = dir.join("frag.glsl.tmp");
+    std::fs::copy(shader_path("frag2.glsl"), &tmp).unwrap();
+    std::fs::rename(&tmp, &frag).unwrap();
+    let message = next_message(&watch);
+    assert_eq!(message.changed, vec![ShaderKind::Fragment]);
+
+    std::fs::remove_file(&frag).unwrap();
+    expect_error(&watch);
+
+    std::fs::copy(shader_path("frag1.glsl"), &frag).unwrap();
+    next_message(&watch);
+}
+
+#[test]
+fn test_watch_config() {
+    setup();
+    let dir = temp_dir("watch_config");
+    std::fs::create_dir_all(dir.join("lib")).unwrap();
+    let frag = dir.join("frag.glsl");
+    std::fs::write(
+        &frag,
+        "#version 450
+#extension GL_GOOGLE_include_directive : require
+#include \"color.glsl\"
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+  f_color = vec4(COLOR);
+}
+",
+    )
+    .unwrap();
+    std::fs::write(dir.join("lib/color.glsl"), "#define COLOR BRIGHTNESS\n").unwrap();
+
+    let config = CompileConfig::new()
+        .include_dir(dir.join("lib"))
+        .define("BRIGHTNESS", Some("0.5"))
+        .optimization(shaderc::OptimizationLevel::Performance);
+    let watch = WatchBuilder::new()
+        .stage(&frag, ShaderKind::Fragment)
+        .config(config)
+        .frequency(Duration::from_millis(50))
+        .build()
+        .expect("Failed to create watch");
+    let message = next_message(&watch);
+    assert_eq!(message.shaders.fragment().unwrap().includes.len(), 1);
+}
+
+#[test]
+fn test_watch_state() {
+    setup();
+    let dir = temp_dir("watch_state");
+    let frag = dir.join("frag.glsl");
+    std::fs::copy(shader_path("frag1.glsl"), &frag).unwrap();
+
+    let watch = WatchBuilder::new()
+        .stage(&frag, ShaderKind::Fragment)
+        .frequency(Duration::from_millis(50))
+        .build()
+        .expect("Failed to create watch");
+    next_message(&watch);
+    assert_eq!(watch.generation(), 1);
+    assert!(watch.error().is_none());
+
+    std::fs::write(&frag, "#version 450\nvoid main() { broken }\n").unwrap();
+    expect_error(&watch);
+
+    let state = watch.state();
+    assert_eq!(state.generation, 1);
+    assert!(state.error.is_some());
+    assert!(state.current.is_some());
+    assert_eq!(state.diagnostics[0].severity, Severity::Error);
+
+    std::fs::copy(shader_path("frag2.glsl"), &frag).unwrap();
+    next_message(&watch);
+    assert_eq!(watch.generation(), 2);
+    assert!(watch.error().is_none());
+    assert!(watch.diagnostics().is_empty());
+}
+
+#[test]
+fn test_watch_callback() {
+    setup();
+    let (tx, rx) = std::sync::mpsc::channel();
+    let watch = WatchBuilder::new()
+        .stage(shader_path("frag1.glsl"), ShaderKind::Fragment)
+        .on_reload(move |result| {
+            tx.send((std::thread::current().id(), result.is_ok())).ok();
+        })
+        .build()
+        .expect("Failed to create watch");
+    let (thread, ok) = rx
+        .recv_timeout(Duration::from_secs(5))
+        .expect("Callback was not called");
+    assert!(ok);
+    assert_ne!(thread, std::thread::current().id());
+    next_message(&watch);
+}
+
+#[cfg(feature = "stream")]
+#[test]
+fn test_watch_stream() {
+    use futures::StreamExt;
+
+    setup();
+    let mut stream = WatchBuilder::new()
+        .stage(shader_path("frag1.glsl"), ShaderKind::Fragment)
+        .build()
+        .expect("Failed to create watch")
+        .into_stream();
+    let message = futures::executor::block_on(stream.next())
+        .expect("Stream ended")
+        .expect("Failed to load shaders");
+    assert_eq!(message.changed, vec![ShaderKind::Fragment]);
+    assert_eq!(stream.watch().generation(), 1);
+}
+
+#[test]
+fn test_watch_drop() {
+    setup();
+    let watch = Watch::create(
+        shader_path("vert1.glsl"),
+        shader_path("frag1.glsl"),
+        Duration::from_millis(50),
+    )
+    .expect("Failed to create watch");
+    next_message(&watch);
+    let start = std::time::Instant::now();
+    drop(watch);
+    assert!(start.elapsed() < Duration::from_millis(100));
+}
+
+#[test]
+fn test_watch_poll() {
+    setup();
+    let dir = temp_dir("watch_poll");
+    let frag = dir.join("frag.glsl");
+    std::fs::copy(shader_path("frag1.glsl"), &frag).unwrap();
+
+    let watch = WatchBuilder::new()
+        .stage(&frag, ShaderKind::Fragment)
+        .mode(WatchMode::Poll(Duration::from_millis(50)))
+        .build()
+        .expect("Failed to create watch");
+    next_message(&watch);
+
+    // Make sure the modification time changes on filesystems with coarse timestamps.
+    std::thread::sleep(Duration::from_secs(1));
+    std::fs::copy(shader_path("frag2.glsl"), &frag).unwrap();
+    next_message(&watch);
+}
+
+#[test]
+fn test_shader_kind_from_path() {
+    assert_eq!(shader_kind_from_path("post/bloom.frag"), Some(ShaderKind::Fragment));
+    assert_eq!(shader_kind_from_path("sky.vert.glsl"), Some(ShaderKind::Vertex));
+    assert_eq!(shader_kind_from_path("cull.comp"), Some(ShaderKind::Compute));
+    assert_eq!(shader_kind_from_path("common.glsl"), None);
+    assert_eq!(shader_kind_from_path("notes.txt"), None);
+}
+
+#[test]
+fn test_shader_library() {
+    setup();
+    let dir = temp_dir("library");
+    std::fs::create_dir_all(dir.join("post")).unwrap();
+    std::fs::copy(shader_path("frag1.glsl"), dir.join("post/bloom.frag")).unwrap();
+    std::fs::copy(shader_path("vert1.glsl"), dir.join("quad.vert")).unwrap();
+    std::fs::write(dir.join("readme.txt"), "not a shader").unwrap();
+
+    let library = ShaderLibrary::create(&dir, CompileConfig::new(), Duration::from_millis(50))
+        .expect("Failed to create library");
+    let mut names = Vec::new();
+    for _ in 0..2 {
+        let (name, result) = library
+            .rx
+            .recv_timeout(Duration::from_secs(5))
+            .expect("No message from library");
+        result.expect("Failed to load shader");
+        names.push(name);
+    }
+    names.sort();
+    assert_eq!(names, vec!["post/bloom.frag".to_string(), "quad.vert".to_string()]);
+
+    let bloom = library.get("post/bloom.frag").expect("Missing shader");
+    assert_eq!(bloom.kind, ShaderKind::Fragment);
+    let frag_entry = parse("frag1.glsl", ShaderKind::Fragment);
+    do_test(&bloom.entry.output, &frag_entry.output);
+
+    std::fs::copy(shader_path("frag2.glsl"), dir.join("post/bloom.frag")).unwrap();
+    let (name, result) = library
+        .rx
+        .recv_timeout(Duration::from_secs(5))
+        .expect("Change was not reloaded");
+    assert_eq!(name, "post/bloom.frag");
+    result.expect("Failed to reload shader");
+
+    std::fs::remove_dir_all(dir.join("post")).unwrap();
+    let (name, result) = library
+        .rx
+        .recv_timeout(Duration::from_secs(5))
+        .expect("Removing the directory was not reported");
+    assert_eq!(name, "post/bloom.frag");
+    assert!(result.is_err());
+    assert_eq!(library.names(), vec!["quad.vert".to_string()]);
+}
+
+#[test]
+fn test_shader_library_poll() {
+    setup();
+    let dir = temp_dir("library_poll");
+    std::fs::copy(shader_path("frag1.glsl"), dir.join("bloom.frag")).unwrap();
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(&dir, dir.join("loop")).unwrap();
+
+    let library = LibraryBuilder::new(&dir)
+        .mode(WatchMode::Poll(Duration::from_millis(50)))
+        .build()
+        .expect("Failed to create library");
+    let (name, result) = library
+        .rx
+        .recv_timeout(Duration::from_secs(5))
+        .expect("No message from library");
+    assert_eq!(name, "bloom.frag");
+    result.expect("Failed to load shader");
+    assert!(library.rx.recv_timeout(Duration::from_millis(500)).is_err());
+}
+
+#[test]
+fn test_shader_compiler_session() {
+    setup();
+    let mut compiler = ShaderCompiler::new().expect("Failed to create compiler");
+    for (name, kind) in &[
+        ("vert1.glsl", ShaderKind::Vertex),
+        ("frag1.glsl", ShaderKind::Fragment),
+        ("vert2.glsl", ShaderKind::Vertex),
+        ("frag2.glsl", ShaderKind::Fragment),
+    ] {
+        let session = compiler
+            .load(shader_path(name), *kind)
+            .expect("Failed to compile");
+        let single = shade_runner::load(shader_path(name), Some(shader_path(name)), *kind, None)
+            .expect("Failed to compile");
+        assert_eq!(session.spriv, single.spriv);
+    }
+}
+
+#[test]
+fn test_standard_includes() {
+    setup();
+    let dir = temp_dir("standard_includes");
+    let first = dir.join("first");
+    let second = dir.join("second");
+    std::fs::create_dir_all(&first).unwrap();
+    std::fs::create_dir_all(&second).unwrap();
+    std::fs::write(second.join("color.glsl"), "vec4 color() { return vec4(1.0); }\n").unwrap();
+    let source = "#version 450
+#extension GL_GOOGLE_include_directive : require
+#include <color.glsl>
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+  f_color = color();
+}
+";
+
+    let config = CompileConfig::new().include_dir(&first).include_dir(&second);
+    let mut compiler = ShaderCompiler::with_config(config).expect("Failed to create compiler");
+    let shader = compiler
+        .load_from_string(source, None::<&Path>, ShaderKind::Fragment)
+        .expect("Failed to compile");
+    assert_eq!(shader.includes, vec![second.join("color.glsl").canonicalize().unwrap()]);
+
+    compiler.set_config(CompileConfig::new().include_dir(&first));
+    let error = compiler
+        .load_from_string(source, None::<&Path>, ShaderKind::Fragment)
+        .err()
+        .expect("Compiled without the include");
+    let message = error.to_string();
+    assert!(message.contains(&first.display().to_string()), "{}", message);
+}
+
+#[test]
+fn test_diagnostics() {
+    setup();
+    let dir = temp_dir("diagnostics");
+    let frag = dir.join("main.frag");
+    let broken = dir.join("broken.glsl");
+    std::fs::write(&frag, "#version 450
+#extension GL_GOOGLE_include_directive : require
+#include \"broken.glsl\"
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+  f_color = color();
+}
+").unwrap();
+    std::fs::write(&broken, "vec4 color() {
+  return missing;
+}
+").unwrap();
+
+    let error = load(&frag, Some(&frag), ShaderKind::Fragment, None)
+        .err()
+        .expect("Compiled a broken shader");
+    let diagnostic = error
+        .diagnostics()
+        .iter()
+        .find(|d| d.severity == Severity::Error)
+        .expect("No error diagnostic")
+        .clone();
+    assert_eq!(diagnostic.file, Some(broken.clone()));
+    assert_eq!(diagnostic.line, Some(2));
+    assert!(diagnostic.message.contains("missing"), "{}", diagnostic.message);
+
+    let rendered = diagnostic.to_string();
+    assert!(rendered.starts_with("error: "), "{}", rendered);
+    assert!(rendered.contains(&format!("--> {}:2", broken.display())), "{}", rendered);
+}
+
+#[test]
+fn test_warnings() {
+    setup();
+    let source = "#version 450
+#extension GL_EXT_not_a_real_extension : warn
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+  f_color = vec4(1.0);
+}
+";
+    let shader = load_from_string(source, None::<&Path>, ShaderKind::Fragment, None)
+        .expect("Failed to compile");
+    assert!(!shader.warnings.is_empty());
+    assert!(shader.warnings.iter().all(|w| w.severity == Severity::Warning));
+    assert_eq!(shader.warnings[0].line, Some(2));
+
+    let config = CompileConfig::new().warnings_as_errors();
+    let mut compiler = ShaderCompiler::with_config(config).expect("Failed to create compiler");
+    let error = compiler
+        .load_from_string(source, None::<&Path>, ShaderKind::Fragment)
+        .err()
+        .expect("Compiled with warnings as errors");
+    assert!(!error.diagnostics().is_empty());
+}
+
+#[test]
+fn test_hlsl_entry_points() {
+    setup();
+    let source = "cbuffer Globals : register(b0) {
+  float4 tint;
+};
+
+struct VSInput {
+  float2 position : POSITION;
+};
+
+struct PSInput {
+  float4 position : SV_POSITION;
+  float4 color : COLOR;
+};
+
+PSInput VSMain(VSInput input) {
+  PSInput output;
+  output.position = float4(input.position, 0.0, 1.0);
+  output.color = tint;
+  return output;
+}
+
+float4 PSMain(PSInput input) : SV_TARGET {
+  return input.color * tint;
+}
+";
+    let config = CompileConfig::new()
+        .source_language(shaderc::SourceLanguage::HLSL)
+        .binding_base(shaderc::ResourceKind::Buffer, 2);
+
+    let mut compiler = ShaderCompiler::with_config(config.clone().entry_point("VSMain"))
+        .expect("Failed to create compiler");
+    let vertex = compiler
+        .load_from_string(source, None::<&Path>, ShaderKind::Vertex)
+        .expect("Failed to compile vertex");
+    assert_eq!(vertex.entry_point, "VSMain");
+    let entry = shade_runner::parse(&vertex).expect("Failed to reflect vertex");
+    let inputs = entry.input.expect("No vertex input").inputs;
+    assert_eq!(inputs.len(), 1);
+    assert_eq!(inputs[0].format, Format::R32G32Sfloat);
+
+    compiler.set_config(config.entry_point("PSMain"));
+    let fragment = compiler
+        .load_from_string(source, None::<&Path>, ShaderKind::Fragment)
+        .expect("Failed to compile fragment");
+    assert_eq!(fragment.entry_point, "PSMain");
+    let entry = shade_runner::parse(&fragment).expect("Failed to reflect fragment");
+    let set = &entry.layout.layout_data.descriptions[&0];
+    assert!(set.contains_key(&2), "buffer wasn't shifted to binding 2");
+
+    let dir = temp_dir("hlsl_entry_points");
+    let path = dir.join("shader.hlsl");
+    std::fs::write(&path, source).unwrap();
+    let watch = WatchBuilder::new()
+        .stage_with_entry(&path, ShaderKind::Vertex, "VSMain")
+        .stage_with_entry(&path, ShaderKind::Fragment, "PSMain")
+        .config(CompileConfig::new().source_language(shaderc::SourceLanguage::HLSL))
+        .build()
+        .expect("Failed to create watch");
+    let message = next_message(&watch);
+    assert_eq!(message.shaders.vertex().unwrap().entry_point, "VSMain");
+    assert_eq!(message.shaders.fragment().unwrap().entry_point, "PSMain");
+}
+
+#[test]
+fn test_load_auto() {
+    setup();
+    let dir = temp_dir("load_auto");
+    let vert = dir.join("quad.vert");
+    let frag = dir.join("quad.glsl");
+    std::fs::write(&vert, "#version 450
+layout(location = 0) in vec2 position;
+
+void main() {
+  gl_Position = vec4(position, 0.0, 1.0);
+}
+").unwrap();
+    std::fs::write(&frag, "#version 450
+#pragma shader_stage(fragment)
+layout(location = 0) out vec4 f_color;
+
+void main() {
+  f_color = vec4(1.0);
+}
+").unwrap();
+
+    let (kind, _) = load_auto(&vert, None).expect("Failed to compile vertex");
+    assert_eq!(kind, ShaderKind::Vertex);
+    let (kind, _) = load_auto(&frag, None).expect("Failed to compile fragment");
+    assert_eq!(kind, ShaderKind::Fragment);
+
+    let watch = WatchBuilder::new()
+        .auto_stage(&frag)
+        .auto_stage(&vert)
+        .build()
+        .expect("Failed to create watch");
+    let message = next_message(&watch);
+    assert!(message.shaders.vertex().is_some());
+    assert!(message.shaders.fragment().is_some());
+}
+
+#[test]
+fn test_permutations() {
+    setup();
+    let source = "#version 450
+layout(location = 0) out vec4 f_color;
+#ifdef SHADOWS
+layout(set = 0, binding = 0) uniform sampler2D shadow_map;
+#endif
+
+void main() {
+  f_color = vec4(float(MSAA));
+#ifdef SHADOWS
+  f_color *= texture(shadow_map, vec2(0.0));
+#endif
+}
+";
+    let permutations = Permutations::new().flag("SHADOWS").values("MSAA", &["1", "4"]);
+    let combinations = permutations.combinations();
+    assert_eq!(combinations.len(), 4);
+
+    let mut compiler = ShaderCompiler::new().expect("Failed to create compiler");
+    let shaders = permutations.compile_all(&mut compiler, source, None::<&Path>, ShaderKind::Fragment);
+    assert_eq!(shaders.len(), 4);
+    for (defines, result) in &shaders {
+        let permutation = result.as_ref().expect("Failed to compile permutation");
+        let num_sets = permutation.entry.layout.layout_data.num_sets;
+        if defines.contains_key("SHADOWS") {
+            assert_eq!(num_sets, 1);
+        } else {
+            assert_eq!(num_sets, 0);
+        }
+    }
+
+    let mut shadows = Defines::new();
+    shadows.insert("SHADOWS".to_string(), None);
+    shadows.insert("MSAA".to_string(), Some("4".to_string()));
+    let subset = compile_permutations(&mut compiler, source, None::<&Path>, ShaderKind::Fragment, &[shadows.clone()]);
+    assert_eq!(subset.len(), 1);
+    assert!(subset[&shadows].is_ok());
+}
+
+#[test]
+fn test_targets() {
+    setup();
+    let source = "#version 450
+layout(local_size_x = 64) in;
+
+layout(set = 0, binding = 0) buffer Data {
+  float values[];
+} data;
+
+layout(push_constant) uniform PushConstants {
+  float scale;
+} pc;
+
+void main() {
+  data.values[gl_GlobalInvocationID.x] *= pc.scale;
+}
+";
+    for target in &[Target::Vulkan1_0, Target::Vulkan1_1, Target::Vulkan1_2] {
+        let config = CompileConfig::new().target(*target);
+        let mut compiler = ShaderCompiler::with_config(config).expect("Failed to create compiler");
+        let shader = compiler
+            .load_compute_from_string(source, None::<&Path>)
+            .expect("Failed to compile");
+        assert_eq!(shader.spirv_version(), Some(target.spirv_version()), "{:?}", target);
+
+        let entry = parse_compute(&shader).expect("Failed to reflect");
+        let layout_data = &entry.layout.layout_data;
+        assert_eq!(layout_data.num_constants, 1, "{:?}", target);
+        match &layout_data.descriptions[&0][&0].ty {
+            DescriptorDescTy::Buffer(desc) => assert!(desc.storage, "{:?}", target),
+            _ => panic!("{:?} didn't reflect a storage buffer", target),
+        }
+    }
+
+    // The target applies even if the config is set after it.
+    let dir = temp_dir("targets");
+    let path = dir.join("shader.comp");
+    std::fs::write(&path, source).unwrap();
+    let watch = WatchBuilder::new()
+        .stage(&path, ShaderKind::Compute)
+        .target(Target::Vulkan1_1)
+        .config(CompileConfig::new())
+        .build()
+        .expect("Failed to create watch");
+    let message = next_message(&watch);
+    let shader = &message.shaders.stages[0].1;
+    assert_eq!(shader.spirv_version(), Some(Target::Vulkan1_1.spirv_version()));
+}
+
+fn spirv_bytes(shader: &CompiledShader, big_endian: bool) -> Vec<u8> {
+    shader
+        .spriv
+        .iter()
+        .flat_map(|w| if big_endian { w.to_be_bytes() } else { w.to_le_bytes() }.to_vec())
+        .collect()
+}
+
+#[test]
+fn test_load_spirv() {
+    setup();
+    let source = "#version 450
+layout(location = 0) in vec4 color;
+layout(location = 0) out vec4 f_color;
+
+void main() {
+  f_color = color;
+}
+";
+    let compiled = load_from_string(source, None::<&Path>, ShaderKind::Fragment, None)
+        .expect("Failed to compile");
+    let dir = temp_dir("load_spirv");
+    let path = dir.join("color.frag.spv");
+    std::fs::write(&path, spirv_bytes(&compiled, false)).unwrap();
+
+    let loaded = load_spirv(&path).expect("Failed to load SPIR-V");
+    assert_eq!(loaded.spriv, compiled.spriv);
+    assert_eq!(loaded.entry_point, "main");
+    let entry = shade_runner::parse(&loaded).expect("Failed to reflect");
+    assert_eq!(entry.input.expect("No input").inputs.len(), 1);
+
+    let swapped = load_spirv_bytes(&spirv_bytes(&compiled, true)).expect("Failed to load big endian");
+    assert_eq!(swapped.spriv, compiled.spriv);
+
+    let bytes = spirv_bytes(&compiled, false);
+    match load_spirv_bytes(&bytes[..bytes.len() - 1]) {
+        Err(Error::Spirv(SpirvError::Length(_))) => (),
+        _ => panic!("Loaded SPIR-V with a bad length"),
+    }
+    match load_spirv_bytes(&[0u8; 20]) {
+        Err(Error::Spirv(SpirvError::Magic(0))) => (),
+        _ => panic!("Loaded SPIR-V with a bad magic number"),
+    }
+
+    let watch = WatchBuilder::new()
+        .auto_stage(&path)
+        .build()
+        .expect("Failed to create watch");
+    let first = next_message(&watch);
+    assert!(first.shaders.fragment().is_some());
+
+    let replaced = load_from_string(&source.replace("f_color = color;", "f_color = color * 2.0;"), None::<&Path>, ShaderKind::Fragment, None)
+        .expect("Failed to compile");
+    std::fs::write(&path, spirv_bytes(&replaced, false)).unwrap();
+    let second = next_message(&watch);
+    assert_eq!(second.shaders.fragment().unwrap().spriv, replaced.spriv);
+}
+
+fn assert_open_error(result: Result<CompiledShader, Error>, path: &Path) {
+    match result {
+        Err(Error::Compile(CompileError::Open(p, _))) => assert_eq!(p, path),
+        Err(e) => panic!("Expected an open error for {}, got {}", path.display(), e),
+        Ok(_) => panic!("Loaded {}", path.display()),
+    }
+}
+
+#[test]
+fn test_load_errors() {
+    setup();
+    let dir = temp_dir("load_errors");
+
+    let missing = dir.join("missing.frag");
+    assert_open_error(load(&missing, None, ShaderKind::Fragment, None), &missing);
+    assert_open_error(load_compute(&missing, None), &missing);
+
+    let directory = dir.join("directory.frag");
+    std::fs::create_dir_all(&directory).unwrap();
+    assert_open_error(load(&directory, None, ShaderKind::Fragment, None), &directory);
+
+    let invalid = dir.join("invalid.frag");
+    std::fs::write(&invalid, &[0x23, 0x76, 0xff, 0xfe, 0x0a]).unwrap();
+    assert_open_error(load(&invalid, None, ShaderKind::Fragment, None), &invalid);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let locked = dir.join("locked.frag");
+        std::fs::write(&locked, "#version 450\nvoid main() {}\n").unwrap();
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o000)).unwrap();
+        // Root can read the file anyway.
+        if std::fs::File::open(&locked).is_err() {
+            assert_open_error(load(&locked, None, ShaderKind::Fragment, None), &locked);
+        }
+        std::fs::set_permissions(&locked, std::fs::Permissions::from_mode(0o644)).unwrap();
+    }
+}
+
+#[test]
+fn test_source_file_names() {
+    setup();
+    let dir = temp_dir("source_file_names");
+    let shaders = dir.join("shaders");
+    let common = dir.join("common");
+    std::fs::create_dir_all(&shaders).unwrap();
+    std::fs::create_dir_all(&common).unwrap();
+    std::fs::write(shaders.join("color.glsl"), "vec4 color() { return vec4(1.0); }\n").unwrap();
+    std::fs::write(common.join("scale.glsl"), "float scale() { return 2.0; }\n").unwrap();
+    let main = shaders.join("main.frag");
+    std::fs::write(&main, "#version 450
+#extension GL_GOOGLE_include_directive : require
+#include \"color.glsl\"
+#include \"scale.glsl\"
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+  f_color = color() * scale();
+}
+").unwrap();
+
+    // color.glsl is next to the shader and scale.glsl is in the include path.
+    let shader = load(&main, Some(&common), ShaderKind::Fragment, None).expect("Failed to compile");
+    assert_eq!(shader.includes.len(), 2);
+
+    let broken = shaders.join("broken.frag");
+    std::fs::write(&broken, "#version 450
+void main() {
+  missing();
+}
+").unwrap();
+    let error = load(&broken, Some(&common), ShaderKind::Fragment, None)
+        .err()
+        .expect("Compiled a broken shader");
+    assert_eq!(error.diagnostics()[0].file, Some(broken.clone()));
+
+    let error = load_from_string("#version 450
+void main() {
+  missing();
+}
+", Some("virtual/broken.frag"), ShaderKind::Fragment, None)
+        .err()
+        .expect("Compiled a broken shader");
+    assert_eq!(error.diagnostics()[0].file, Some(PathBuf::from("virtual/broken.frag")));
+    assert_eq!(error.diagnostics()[0].line, Some(3));
+}
+
+#[test]
+fn test_memory_source() {
+    setup();
+    let source = std::sync::Arc::new(MemorySource::new());
+    source.insert("shaders/main.frag", "#version 450
+#extension GL_GOOGLE_include_directive : require
+#include \"color.glsl\"
+
+layout(location = 0) out vec4 f_color;
+
+void main() {
+  f_color = color();
+}
+");
+    source.insert("shaders/color.glsl", "vec4 color() { return vec4(1.0); }\n");
+
+    let mut compiler = ShaderCompiler::with_source(CompileConfig::new(), source.clone())
+        .expect("Failed to create compiler");
+    let shader = compiler
+        .load("shaders/main.frag", ShaderKind::Fragment)
+        .expect("Failed to compile from memory");
+    assert_eq!(shader.includes, vec![PathBuf::from("shaders/color.glsl")]);
+
+    // A source without a name has no folder to look next to.
+    source.insert("color.glsl", "vec4 color() { return vec4(1.0); }\n");
+    let main = source.read(Path::new("shaders/main.frag")).unwrap();
+    let unnamed = ShaderCompiler::with_source(CompileConfig::new().include_dir("lib"), source.clone())
+        .expect("Failed to create compiler")
+        .load_from_string(&main, None::<&Path>, ShaderKind::Fragment);
+    assert!(unnamed.is_err());
+    source.remove("color.glsl");
+
+    let watch = WatchBuilder::new()
+        .stage("shaders/main.frag", ShaderKind::Fragment)
+        .source(source.clone())
+        .build()
+        .expect("Failed to create watch");
+    let first = next_message(&watch);
+
+    source.insert("shaders/color.glsl", "vec4 color() { return vec4(0.5); }\n");
+    let second = next_message(&watch);
+    assert_ne!(
+        first.shaders.fragment().unwrap().spriv,
+        second.shaders.fragment().unwrap().spriv
+    );
+
+    source.remove("shaders/color.glsl");
+    expect_error(&watch);
+
+    // Precompiled modules are read from the source too.
+    source.insert("shaders/main.frag.spv", spirv_bytes(&shader, false));
+    let loaded = compiler
+        .load_spirv("shaders/main.frag.spv")
+        .expect("Failed to load SPIR-V from memory");
+    assert_eq!(loaded.spriv, shader.spriv);
+}
+
+#[test]
+fn test_memory_library() {
+    setup();
+    let source = std::sync::Arc::new(MemorySource::new());
+    source.insert("shaders/post/bloom.frag", INCLUDING_FRAG);
+    source.insert("shaders/../shaders/common/common.glsl", "vec4 color() { return vec4(1.0); }\n");
+    assert!(source.exists(Path::new("./shaders/common/common.glsl")));
+
+    let library = LibraryBuilder::new("shaders")
+        .source(source.clone())
+        .build()
+        .expect("Failed to create library");
+    let (name, result) = library
+        .rx
+        .recv_timeout(Duration::from_secs(5))
+        .expect("No message from library");
+    assert_eq!(name, "post/bloom.frag");
+    let bloom = result.expect("Failed to load shader");
+    assert_eq!(bloom.shader.includes, vec![PathBuf::from("shaders/common/common.glsl")]);
+
+    source.insert("shaders/common/common.glsl", "vec4 color() { return vec4(0.5); }\n");
+    let (name, result) = library
+        .rx
+        .recv_timeout(Duration::from_secs(5))
+        .expect("Include change was not reloaded");
+    assert_eq!(name, "post/bloom.frag");
+    result.expect("Failed to reload shader");
+
+    source.remove("shaders/post/bloom.frag");
+    let (name, result) = library
+        .rx
+        .recv_timeout(Duration::from_secs(5))
+        .expect("Remove was not reported");
+    assert_eq!(name, "post/bloom.frag");
+    assert!(result.is_err());
+    assert!(library.names().is_empty());
+}
+
+#[test]
+fn test_compile_cache() {
+    setup();
+    let dir = temp_dir("compile_cache");
+    std::fs::create_dir_all(dir.join("shaders")).unwrap();
+    std::fs::create_dir_all(dir.join("common")).unwrap();
+    let frag = dir.join("shaders").join("main.frag");
+    let include = dir.join("common").join("common.glsl");
+    std::fs::write(&frag, INCLUDING_FRAG).unwrap();
+    std::fs::write(&include, "vec4 color() { return vec4(1.0); }\n").unwrap();
+
+    let cache = CompileCache::new(dir.join("cache"));
+    let config = CompileConfig::new().cache(cache.clone());
+    let first = ShaderCompiler::with_config(config.clone())
+        .expect("Failed to create compiler")
+        .load(&frag, ShaderKind::Fragment)
+        .expect("Failed to compile");
+    assert_eq!(cache.stats(), CacheStats { hits: 0, misses: 1, evictions: 0 });
+
+    // A new session, like the next run of the program.
+    let second = ShaderCompiler::with_config(config.clone())
+        .expect("Failed to create compiler")
+        .load(&frag, ShaderKind::Fragment)
+        .expect("Failed to compile");
+    assert_eq!(cache.stats().hits, 1);
+    assert_eq!(first.spriv, second.spriv);
+    assert_eq!(first.includes, second.includes);
+
+    // A watch gets its reflection from the entry on a hit.
+    let watch = WatchBuilder::new()
+        .stage(&frag, ShaderKind::Fragment)
+        .config(config.clone())
+        .build()
+        .expect("Failed to create watch");
+    let message = next_message(&watch);
+    assert_eq!(cache.stats().hits, 2);
+    let reflected = parse("frag1.glsl", ShaderKind::Fragment);
+    do_test(&message.entry.output, &reflected.output);
+    do_test(&shade_runner::parse(&second).unwrap().output, &reflected.output);
+    drop(watch);
+
+    // Changing an included file is a miss.
+    std::fs::write(&include, "vec4 color() { return vec4(0.5); }\n").unwrap();
+    let third = ShaderCompiler::with_config(config.clone())
+        .expect("Failed to create compiler")
+        .load(&frag, ShaderKind::Fragment)
+        .expect("Failed to compile");
+    assert_eq!(cache.stats().misses, 2);
+    assert_ne!(first.spriv, third.spriv);
+
+    // The same source at another path is a miss.
+    let copy = dir.join("shaders").join("copy.frag");
+    std::fs::write(&copy, INCLUDING_FRAG).unwrap();
+    ShaderCompiler::with_config(config)
+        .expect("Failed to create compiler")
+        .load(&copy, ShaderKind::Fragment)
+        .expect("Failed to compile");
+    assert_eq!(cache.stats().misses, 3);
+
+    // Other settings are a miss and a tiny cache evicts.
+    let small = CompileCache::new(dir.join("cache")).max_size(1);
+    let config = CompileConfig::new().define("UNUSED", None).cache(small.clone());
+    ShaderCompiler::with_config(config)
+        .expect("Failed to create compiler")
+        .load(&frag, ShaderKind::Fragment)
+        .expect("Failed to compile");
+    assert_eq!(small.stats().misses, 1);
+    assert!(small.stats().evictions > 0);
+
+    cache.clear().expect("Failed to clear cache");
+
+    // A hit counts as a use, so the entry that wasn't used is evicted.
+    let compile = |cache: &CompileCache, define: &str| {
+        ShaderCompiler::with_config(CompileConfig::new().define(define, None).cache(cache.clone()))
+            .expect("Failed to create compiler")
+            .load(&frag, ShaderKind::Fragment)
+            .expect("Failed to compile");
+        // Make sure the modification times differ on filesystems with coarse timestamps.
+        std::thread::sleep(Duration::from_secs(1));
+    };
+    compile(&cache, "FIRST");
+    compile(&cache, "SECOND");
+    let size = std::fs::read_dir(cache.dir())
+        .unwrap()
+        .map(|e| e.unwrap().metadata().unwrap().len())
+        .sum::<u64>();
+    let lru = CompileCache::new(cache.dir()).max_size(size + size / 4);
+    compile(&lru, "FIRST");
+    compile(&lru, "THIRD");
+    compile(&lru, "FIRST");
+    assert_eq!(lru.stats(), CacheStats { hits: 2, misses: 1, evictions: 1 });
+    compile(&lru, "SECOND");
+    assert_eq!(lru.stats().misses, 2);
+}
+
+#[test]
+fn test_compile_batch() {
+    setup();
+    let mut jobs = Vec::new();
+    let mut expected = Vec::new();
+    for i in 1..=4 {
+        for (name, kind) in &[(format!("vert{}.glsl", i), ShaderKind::Vertex), (format!("frag{}.glsl", i), ShaderKind::Fragment)] {
+            jobs.push(BatchJob::file(shader_path(name), *kind));
+            expected.push(load(shader_path(name), None, *kind, None).expect("Failed to compile").spriv);
+        }
+    }
+    jobs.push(BatchJob::source("#version 450\nvoid main() { missing(); }\n", Some("broken.frag"), ShaderKind::Fragment));
+
+    let batch = compile_batch_with_threads(jobs, 3);
+    assert_eq!(batch.results.len(), expected.len() + 1);
+    for (job, spriv) in batch.results.iter().zip(&expected) {
+        let shader = job.result.as_ref().expect("Failed to compile job");
+        assert_eq!(&shader.spriv, spriv);
+        assert!(job.time <= batch.total);
+    }
+    let broken = batch.results.last().unwrap().result.as_ref().err().expect("Compiled a broken shader");
+    assert_eq!(broken.diagnostics()[0].file, Some(PathBuf::from("broken.frag")));
+
+    assert!(compile_batch(Vec::new()).results.is_empty());
+}